@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use tracing::warn;
+
+use crate::command::{AssetMetadata, ArtHeader};
+use crate::crypto;
+
+/// Decrypted contents of an Art.dat, kept in memory so patches can be applied without touching
+/// the disk.
+#[derive(Debug, Clone, Default)]
+pub struct ArtArchive {
+    pub entries: Vec<ArtEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtEntry {
+    /// Path of the asset, starting with `assets/`
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl ArtArchive {
+    /// Decrypts and parses an Art.dat
+    pub fn decrypt(mut data: Vec<u8>, art_key: &str) -> anyhow::Result<Self> {
+        let enc_key = crypto::to_key_array(art_key);
+        crypto::decrypt(enc_key.as_slice(), data.as_mut_slice());
+        Self::from_bytes(&data)
+    }
+
+    /// Parses a decrypted Art.dat
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 2 {
+            anyhow::bail!("Art.dat is too short");
+        }
+        // A set sign bit marks a 4 byte header length, written for headers too long for the game's readInt16
+        let first = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let (len, mut index) = if first & 0x8000 != 0 && data.len() >= 4 {
+            let second = u16::from_le_bytes([data[2], data[3]]) as usize;
+            (((first & 0x7FFF) << 16) | second, 4)
+        } else {
+            (first, 2)
+        };
+
+        let header = data.get(index..index + len)
+            .context("Art.dat header is out of bounds")?;
+        let header = std::str::from_utf8(header)
+            .context("Failed to read header string")?;
+        let assets = haxeformat::from_str::<ArtHeader>(header)
+            .context("Failed to parse header string")?;
+        index += len;
+
+        let mut entries = Vec::with_capacity(assets.len());
+        for asset in assets {
+            let asset_bytes = data.get(index..index + asset.size)
+                .with_context(|| format!("Data of asset {} is out of bounds", asset.name))?;
+            index += asset.size;
+            entries.push(ArtEntry { name: asset.name, data: asset_bytes.to_vec() });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Serializes and encrypts the archive into an Art.dat
+    pub fn encrypt(&self, art_key: &str) -> anyhow::Result<Vec<u8>> {
+        let mut out = self.to_bytes()?;
+        let enc_key = crypto::to_key_array(art_key);
+        crypto::encrypt(enc_key.as_slice(), out.as_mut_slice());
+        Ok(out)
+    }
+
+    /// Serializes the archive into an unencrypted Art.dat
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let assets = self.entries.iter()
+            .map(|entry| AssetMetadata { name: entry.name.clone(), size: entry.data.len() })
+            .collect::<ArtHeader>();
+        let mut header = haxeformat::to_string(&assets)?.into_bytes();
+
+        let data_len = self.entries.iter().map(|entry| entry.data.len()).sum::<usize>();
+        let mut out = Vec::with_capacity(header.len() + data_len + 4);
+        let header_len = header.len() as i32;
+        if header_len > i16::MAX as i32 {
+            warn!("!!! Header length {} exceeds {}. This assets file will only work with a modded game !!!", header_len, i16::MAX);
+            let len_one = (header_len & 0xFFFF) as u16;
+            // set sign bit to 1 as a marker for the modded readInt16 to read 4 bytes instead of 2
+            let len_two = ((header_len >> 16) as u16) | 0x8000;
+            out.extend_from_slice(len_two.to_le_bytes().as_ref());
+            out.extend_from_slice(len_one.to_le_bytes().as_ref());
+        } else {
+            out.extend_from_slice((header.len() as u16).to_le_bytes().as_ref());
+        }
+        out.append(&mut header);
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.data);
+        }
+        Ok(out)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ArtEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ArtEntry> {
+        self.entries.iter_mut().find(|entry| entry.name == name)
+    }
+
+    /// Replaces the data of an entry, or appends a new entry if it doesn't exist
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) {
+        match self.get_mut(name) {
+            Some(entry) => entry.data = data,
+            None => self.entries.push(ArtEntry { name: name.to_string(), data }),
+        }
+    }
+
+    /// Removes an entry, returning whether it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.name != name);
+        self.entries.len() != len
+    }
+
+    /// Writes every entry to a file in the output directory. Entries trying to escape the output
+    /// directory are skipped.
+    pub fn write_to(&self, output: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(output)?;
+        let abs_output = output.canonicalize()?;
+
+        for entry in &self.entries {
+            let path = abs_output.join(&entry.name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+                if !parent.canonicalize()?.starts_with(&abs_output) {
+                    warn!("Skipping asset: {} (Tried escaping output directory)", entry.name);
+                    continue;
+                }
+            }
+
+            fs::write(path, &entry.data)
+                .with_context(|| format!("Failed to write asset {} to file", entry.name))?;
+        }
+        Ok(())
+    }
+}
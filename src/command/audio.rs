@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::{info, warn};
+use crate::Args;
+use crate::command::unpack::{export_file_name, find_input};
+use crate::fsb::decode::decode_sample;
+use crate::fsb::Fsb5;
+use crate::fsb::wav::write_wav;
+use crate::unity;
+use crate::unity::audio::AudioClip;
+use crate::unity::context::AssetsContext;
+use crate::unity::resource::ResourceResolver;
+
+/// Decodes audio clips of an assets file, or the samples of an FSB file, to WAV files.
+/// If names are given, only the audio clips with these names are exported.
+pub fn export(args: &Args, input: &Option<PathBuf>, output: &Path, names: &[String]) -> anyhow::Result<()> {
+    let input = find_input(args, input)?;
+    fs::create_dir_all(output)
+        .context("Failed to create output directory")?;
+
+    if input.extension() == Some(OsStr::new("fsb")) {
+        let name = input.file_stem()
+            .and_then(|name| name.to_str())
+            .context("Invalid FSB file name")?;
+        let data = fs::read(&input)
+            .context("Failed to read FSB file")?;
+        let count = export_fsb(&data, name, output)?;
+        info!("Exported {} samples", count);
+        return Ok(());
+    }
+
+    let context = AssetsContext::load(&input)?;
+    let resources = ResourceResolver::new(&input)?;
+    let mut wanted = names.iter().map(|name| name.as_str()).collect::<HashSet<_>>();
+    let mut used_names = HashSet::new();
+    let mut exported = 0;
+    for obj in context.objects_of_class(AssetsContext::ROOT, unity::AUDIO_CLIP_CLASS) {
+        let clip: AudioClip = context.read_object(obj, ())?;
+        if !names.is_empty() && !wanted.remove(clip.object_name.as_str()) {
+            continue;
+        }
+
+        let name = export_file_name(&clip.object_name, obj.path_id, &mut used_names);
+        let result = resources.read(&clip.resource)
+            .and_then(|data| export_fsb(&data, &name, output));
+        match result {
+            Ok(_) => exported += 1,
+            Err(e) => warn!("Skipping audio clip {}: {}", clip.object_name, e),
+        }
+    }
+
+    if !wanted.is_empty() {
+        let mut missing = wanted.into_iter().collect::<Vec<_>>();
+        missing.sort();
+        warn!("Audio clips not found: {}", missing.join(", "));
+    }
+    info!("Exported {} audio clips", exported);
+
+    Ok(())
+}
+
+/// Decodes every sample of an FSB file. Sound banks with multiple samples get the sample index
+/// appended to the file name.
+fn export_fsb(data: &[u8], name: &str, output: &Path) -> anyhow::Result<usize> {
+    let fsb = Fsb5::read(&mut Cursor::new(data))?;
+    fsb.validate(data.len() as u64)?;
+
+    for (index, sample) in fsb.samples.iter().enumerate() {
+        let pcm = decode_sample(&fsb, data, index)?;
+        let file_name = if fsb.samples.len() == 1 {
+            format!("{}.wav", name)
+        } else {
+            format!("{}-{}.wav", name, index)
+        };
+        write_wav(&output.join(file_name), sample.channels, sample.frequency, &pcm)?;
+    }
+    Ok(fsb.samples.len())
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use binrw::BinRead;
+use binrw::io::BufReader;
+use serde::{Deserialize, Serialize};
+use crate::command::ASSETS_FILE_NAME;
+use crate::command::manifest::hash_file;
+use crate::unity::AssetsFile;
+
+/// Name of the file in the game's data folder that describes the backups of the game files
+pub const BACKUPS_FILE_NAME: &str = "papers-tools-backups.json";
+
+/// Identifies an installed version of the game, to detect game updates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameBuild {
+    /// Unity version the assets were built with
+    pub unity_version: String,
+    /// Hash of the il2cpp global-metadata.dat, which changes with every build of the game code
+    pub metadata_hash: String,
+}
+
+impl GameBuild {
+    /// Identifies the game installed in the given data folder
+    pub fn detect(game_dir: &Path) -> anyhow::Result<Self> {
+        // fall back to the backup if the live file is missing
+        let mut assets_path = game_dir.join(ASSETS_FILE_NAME);
+        if !assets_path.is_file() {
+            assets_path = game_dir.join(format!("{}-bak", ASSETS_FILE_NAME));
+        }
+        let mut input = BufReader::new(File::open(&assets_path)
+            .with_context(|| format!("Failed to open {}", assets_path.display()))?);
+        let assets = AssetsFile::read(&mut input)
+            .with_context(|| format!("Failed to read {}", assets_path.display()))?;
+        let metadata_hash = hash_file(&game_dir.join("il2cpp_data/Metadata/global-metadata.dat"))?;
+
+        Ok(Self {
+            unity_version: assets.content.unity_version.to_string(),
+            metadata_hash,
+        })
+    }
+}
+
+impl std::fmt::Display for GameBuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unity {}, metadata {}", self.unity_version, self.metadata_hash)
+    }
+}
+
+/// Backups of the original game files, with the game build they were taken from
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupRecords {
+    pub files: Vec<BackupRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupRecord {
+    /// Path of the original file relative to the data folder
+    pub path: String,
+    /// Path of the backup relative to the data folder
+    pub backup: String,
+    pub hash: String,
+    pub build: GameBuild,
+}
+
+impl BackupRecords {
+    pub fn path(game_dir: &Path) -> PathBuf {
+        game_dir.join(BACKUPS_FILE_NAME)
+    }
+
+    pub fn load(game_dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(game_dir);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(&path)
+            .context("Failed to read backup records")?;
+        serde_json::from_str(&data)
+            .context("Failed to parse backup records")
+    }
+
+    pub fn save(&self, game_dir: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_string_pretty(self)
+            .context("Failed to serialize backup records")?;
+        fs::write(Self::path(game_dir), data)
+            .context("Failed to write backup records")?;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&BackupRecord> {
+        self.files.iter().find(|record| record.path == path)
+    }
+
+    /// Records the current content of a backup file
+    pub fn record(&mut self, game_dir: &Path, path: &str, backup: &str, build: &GameBuild) -> anyhow::Result<()> {
+        let hash = hash_file(&game_dir.join(backup))?;
+        self.files.retain(|record| record.path != path);
+        self.files.push(BackupRecord {
+            path: path.to_string(),
+            backup: backup.to_string(),
+            hash,
+            build: build.clone(),
+        });
+        Ok(())
+    }
+}
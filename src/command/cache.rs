@@ -0,0 +1,82 @@
+use std::env::temp_dir;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rand::random;
+use tracing::{info, warn};
+
+use crate::command::art::ArtArchive;
+
+/// Number of archives kept in the cache. Older ones are removed when a new one is stored.
+const MAX_ENTRIES: usize = 3;
+
+/// Cache of decrypted vanilla Art.dat archives, so repeated runs don't have to decrypt the same
+/// data again. Entries are addressed by the hash of the file the archive was read from and the
+/// key it was decrypted with.
+pub struct ArtCache {
+    dir: PathBuf,
+}
+
+impl ArtCache {
+    /// Opens the cache in the user's cache directory
+    pub fn open() -> anyhow::Result<Self> {
+        let dir = dirs::cache_dir()
+            .unwrap_or_else(temp_dir)
+            .join("papers-tools")
+            .join("art");
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the cached archive of the given source, if there is one
+    pub fn load(&self, source_hash: &str, art_key: &str) -> Option<ArtArchive> {
+        let path = self.entry_path(source_hash, art_key);
+        let data = fs::read(&path).ok()?;
+        match ArtArchive::from_bytes(&data) {
+            Ok(archive) => {
+                info!("Using cached Art.dat from {}", path.display());
+                Some(archive)
+            }
+            Err(e) => {
+                warn!("Ignoring broken cache entry {}: {}", path.display(), e);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    pub fn store(&self, source_hash: &str, art_key: &str, archive: &ArtArchive) -> anyhow::Result<()> {
+        let path = self.entry_path(source_hash, art_key);
+        // write to a temporary file first, so concurrent runs never read a partial entry
+        let temp = self.dir.join(format!("{}.tmp", random::<u64>()));
+        fs::write(&temp, archive.to_bytes()?)
+            .context("Failed to write cache entry")?;
+        fs::rename(&temp, &path)
+            .context("Failed to write cache entry")?;
+        self.evict();
+        Ok(())
+    }
+
+    fn entry_path(&self, source_hash: &str, art_key: &str) -> PathBuf {
+        let digest = md5::compute(format!("{}:{}", source_hash, art_key));
+        self.dir.join(format!("{:x}.dat", digest))
+    }
+
+    /// Removes the least recently written entries above the limit
+    fn evict(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return };
+        let mut entries = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "dat"))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        for (_, path) in entries.into_iter().skip(MAX_ENTRIES) {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove old cache entry {}: {}", path.display(), e);
+            }
+        }
+    }
+}
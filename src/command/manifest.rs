@@ -0,0 +1,212 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use walkdir::WalkDir;
+
+use crate::command::patch::audio_patcher::DEFAULT_RESOURCE_NAME;
+
+/// Name of the install manifest in the game's data folder
+pub const MANIFEST_FILE_NAME: &str = "papers-tools-manifest.json";
+
+/// Record of every file in the data folder that was created or overwritten by the patch command,
+/// so the changes can be reverted exactly.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallManifest {
+    /// Version of papers-tools that applied the patch
+    pub tool_version: String,
+    /// When the patch was applied (RFC 3339)
+    pub timestamp: String,
+    /// Patch directories in the order they were applied
+    #[serde(default)]
+    pub patches: Vec<AppliedPatch>,
+    /// Art.dat key the patch was packed with
+    pub art_key: String,
+    pub files: Vec<InstalledFile>,
+    /// Single patch directory written by earlier versions, moved to `patches` on load
+    #[serde(default, skip_serializing)]
+    patch_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing)]
+    patch_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedPatch {
+    pub dir: PathBuf,
+    /// Hash over the paths and contents of all files in the patch directory
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledFile {
+    /// Path relative to the data folder
+    pub path: String,
+    #[serde(flatten)]
+    pub change: FileChange,
+    /// Hash of the file right after patching, used to detect changes made afterward
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patched_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "camelCase")]
+pub enum FileChange {
+    /// An existing file was overwritten. The original is kept in the backup file.
+    #[serde(rename_all = "camelCase")]
+    Overwritten { backup: String, original_hash: String },
+    /// The file didn't exist before patching
+    Created,
+}
+
+impl InstallManifest {
+    /// Creates an empty manifest for a patch that is about to be applied
+    pub fn new(patch_dirs: &[PathBuf], art_key: &str) -> anyhow::Result<Self> {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .context("Failed to format timestamp")?;
+        let patches = patch_dirs.iter()
+            .map(|dir| Ok(AppliedPatch {
+                dir: fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()),
+                hash: hash_dir(dir)?,
+            }))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp,
+            patches,
+            art_key: art_key.to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub fn path(game_dir: &Path) -> PathBuf {
+        game_dir.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Loads the manifest of the given data folder, if the game was patched
+    pub fn load(game_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(game_dir);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)
+            .context("Failed to read install manifest")?;
+        let mut manifest: Self = serde_json::from_str(&data)
+            .context("Failed to parse install manifest")?;
+        if let (Some(dir), Some(hash)) = (manifest.patch_dir.take(), manifest.patch_hash.take()) {
+            manifest.patches.push(AppliedPatch { dir, hash });
+        }
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, game_dir: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_string_pretty(self)
+            .context("Failed to serialize install manifest")?;
+        fs::write(Self::path(game_dir), data)
+            .context("Failed to write install manifest")?;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&InstalledFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    /// Records that a file is overwritten, with its original content in `backup`
+    pub fn record_overwritten(&mut self, game_dir: &Path, path: &str, backup: &str) -> anyhow::Result<()> {
+        let original_hash = hash_file(&game_dir.join(backup))?;
+        self.record(InstalledFile {
+            path: path.to_string(),
+            change: FileChange::Overwritten { backup: backup.to_string(), original_hash },
+            patched_hash: None,
+        });
+        Ok(())
+    }
+
+    /// Records that a file is created. `previous` is the manifest of an earlier patch, which may
+    /// have created the file already. Files that exist but weren't created by the tool are never
+    /// overwritten, since there would be no way to restore them.
+    pub fn record_created(&mut self, game_dir: &Path, path: &str, previous: &InstallManifest) -> anyhow::Result<()> {
+        let created_before = previous.get(path)
+            .is_some_and(|file| file.change == FileChange::Created);
+        // versions without a manifest always wrote the patched audio to the default resource file
+        let created_by_legacy = previous.files.is_empty() && path == DEFAULT_RESOURCE_NAME;
+        if game_dir.join(path).exists() && !created_before && !created_by_legacy {
+            anyhow::bail!("{} already exists in the game directory and wasn't created by papers-tools, refusing to overwrite it. \
+                If an earlier patch created it, run revert first.", path);
+        }
+        self.record(InstalledFile { path: path.to_string(), change: FileChange::Created, patched_hash: None });
+        Ok(())
+    }
+
+    /// Adds the entries of another manifest that aren't part of this one
+    pub fn merge(&mut self, other: &InstallManifest) {
+        for file in &other.files {
+            if self.get(&file.path).is_none() {
+                self.files.push(file.clone());
+            }
+        }
+    }
+
+    /// Stores the current hashes of all recorded files that don't have one yet
+    pub fn record_patched_hashes(&mut self, game_dir: &Path) -> anyhow::Result<()> {
+        for file in &mut self.files {
+            if file.patched_hash.is_none() {
+                file.patched_hash = Some(hash_file(&game_dir.join(&file.path))?);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, file: InstalledFile) {
+        self.files.retain(|existing| existing.path != file.path);
+        self.files.push(file);
+    }
+}
+
+/// MD5 hash of a file as a hex string
+pub fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut reader = BufReader::new(File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?);
+    let mut context = md5::Context::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        context.consume(&buf[..read]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
+/// MD5 hash over the relative paths and contents of all files in a directory
+pub fn hash_dir(dir: &Path) -> anyhow::Result<String> {
+    let mut files = WalkDir::new(dir)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to list patch directory")?
+        .into_iter()
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut context = md5::Context::new();
+    for file in files {
+        let relative = file.strip_prefix(dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        context.consume(relative.as_bytes());
+        context.consume(hash_file(&file)?.as_bytes());
+    }
+    Ok(format!("{:x}", context.compute()))
+}
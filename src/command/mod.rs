@@ -10,7 +10,21 @@ pub type ArtHeader = Vec<AssetMetadata>;
 
 pub const DATA_FOLDER_NAME: &str = "PapersPlease_Data";
 
+/// Game files modified by the patch command, relative to the data folder
+pub const ASSETS_FILE_NAME: &str = "sharedassets0.assets";
+pub const LOCALE_FILE_NAME: &str = "StreamingAssets/loc/en.zip";
+
+/// Name of the TextAsset in sharedassets0.assets that contains the encrypted Art.dat
+pub const ART_OBJECT_NAME: &str = "Art.dat";
+
+pub mod art;
+pub mod audio;
+pub mod backup;
+pub mod cache;
+pub mod manifest;
 pub mod pack;
 pub mod unpack;
 pub mod patch;
-pub mod revert;
\ No newline at end of file
+pub mod revert;
+pub mod status;
+pub mod workspace;
\ No newline at end of file
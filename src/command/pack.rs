@@ -2,11 +2,10 @@ use std::ffi::OsStr;
 use std::path::PathBuf;
 
 use anyhow::Context;
-use tracing::{info, warn};
+use tracing::info;
 use walkdir::WalkDir;
 
-use crate::command::AssetMetadata;
-use crate::crypto;
+use crate::command::art::{ArtArchive, ArtEntry};
 
 pub fn pack(art_key: &String, input: &Option<PathBuf>, output: &PathBuf) -> anyhow::Result<()> {
     let input = find_input(input);
@@ -65,9 +64,7 @@ fn find_input(input: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
 
 fn pack_dat(art_key: &String, input: &PathBuf, output: &PathBuf) -> anyhow::Result<()> {
     info!("Packing assets...");
-    let mut assets: Vec<AssetMetadata> = Vec::new();
-    let mut asset_bytes: Vec<u8> = Vec::new();
-    let mut count = 0;
+    let mut archive = ArtArchive::default();
 
     for file in WalkDir::new(input) {
         let file = file.unwrap();
@@ -79,42 +76,18 @@ fn pack_dat(art_key: &String, input: &PathBuf, output: &PathBuf) -> anyhow::Resu
         let mut name = path.strip_prefix(input)?.to_str()
             .context("Failed to convert path to string")?
             .to_string();
-        let size = path.metadata()?.len() as usize;
         if !name.starts_with("assets/") {
             name = format!("assets/{}", name.replace("\\", "/"));
         }
 
-        assets.push(AssetMetadata { name, size });
-
-        asset_bytes.extend_from_slice(&std::fs::read(path)?);
-        count += 1;
-    }
-
-    let header = haxeformat::to_string(&assets)?;
-    let mut header = header.into_bytes();
-    let mut out = Vec::new();
-    let header_len = header.len() as i32;
-    if header_len > i16::MAX as i32 {
-        warn!("!!! Header length {} exceeds {}. This assets file will only work with a modded game !!!", header_len, i16::MAX);
-        let len_one = (header_len & 0xFFFF) as u16;
-        // set sign bit to 1 as a marker for the modded readInt16 to read 4 bytes instead of 2
-        let len_two = ((header_len >> 16) as u16) | 0x8000;
-        out.extend_from_slice(len_two.to_le_bytes().as_ref());
-        out.extend_from_slice(len_one.to_le_bytes().as_ref());
-    } else {
-        out.extend_from_slice((header.len() as u16).to_le_bytes().as_ref());
+        archive.entries.push(ArtEntry { name, data: std::fs::read(path)? });
     }
-    out.append(&mut header);
-    out.append(&mut asset_bytes);
 
     info!("Encrypting assets...");
-    let key = art_key.clone();
-    let enc_key = crypto::to_key_array(key.as_str());
-    let enc_key = enc_key.as_slice();
-    crypto::encrypt(enc_key, out.as_mut_slice());
+    let out = archive.encrypt(art_key)?;
 
     std::fs::write(output, out)?;
-    info!("Packed {} assets", count);
+    info!("Packed {} assets", archive.entries.len());
 
     Ok(())
 }
@@ -1,6 +1,8 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
@@ -10,159 +12,243 @@ use binrw::io::BufReader;
 use tracing::info;
 use walkdir::WalkDir;
 
-use crate::command::pack;
+use crate::command::ART_OBJECT_NAME;
+use crate::command::art::{ArtArchive, ArtEntry};
+use crate::unity;
+use crate::command::patch::report::{PatchReport, ReportedFile};
+use crate::command::patch::staging::StagedWrites;
+use crate::command::patch::watch::Affected;
 use crate::command::patch::xml_patcher;
 use crate::command::unpack::RepackInfo;
 use crate::unity::{AssetsFile, AssetsFileContent, AssetsFileHeader, ObjectInfo};
+use crate::unity::text::TextAsset;
 use crate::unity::util::{AlignedString, AlignmentArgs};
 
-/// Length of the header of the Art.dat object.
-/// The header consists of:
-/// - 4 bytes for object name length
-/// - 7 bytes for object name (Art.dat)
-/// - 1 byte for alignment to next 4-byte block
-/// - 4 bytes for data length
-const ART_OBJ_HEADER_LEN: u64 = 4 + 7 + 1 + 4;
-
+/// Applies the file replacements, xml merges and new files of the patches to the affected Art.dat
+/// entries. Entries are patched from their vanilla version in load order: replacements and new
+/// files of later patches win, xml patches are all merged. Returns whether any entry was affected.
 pub fn patch_assets(
-    patch: &PathBuf,
-    temp_dir: &PathBuf,
-    game_dir: &PathBuf,
-    repack_info: RepackInfo,
-) -> anyhow::Result<PathBuf> { // patched assets directory
+    patches: &[PathBuf],
+    changed: &Affected,
+    vanilla: &ArtArchive,
+    art: &mut ArtArchive,
+    report: &mut PatchReport,
+) -> anyhow::Result<bool> {
+    let names = affected_entries(patches, changed, art)?;
+    if names.is_empty() {
+        return Ok(false);
+    }
+
     info!("Patching assets..");
-    let patched_assets = temp_dir.join("patched");
-    let unpacked = temp_dir.join("unpacked");
-    std::fs::create_dir_all(&patched_assets)
-        .context("Failed to create patched assets directory")?;
-
-    // copy over original files and if they have a patch, apply the patch
-    for file in WalkDir::new(&unpacked) {
-        let file = file.map_err(|e| anyhow::anyhow!("Failed to walk directory: {}", e))?;
-        let rel_path = file.path().strip_prefix(&unpacked)
-            .context("Failed to strip prefix")?;
-        let file_type = file.file_type();
-
-        // if file is a directory, just create it in the patched assets directory
-        if file_type.is_dir() {
-            let target = patched_assets.join(rel_path);
-            std::fs::create_dir_all(&target).context("Failed to create directory")?;
-            continue;
+    for name in &names {
+        report.clear_file(name);
+        let data = match vanilla.get(name) {
+            Some(entry) => patch_entry(patches, entry, report)?,
+            None => match new_entry(patches, name, report)? {
+                Some(data) => data,
+                None => {
+                    if art.remove(name) {
+                        info!("Removing file no longer in the patch: {}", name);
+                    }
+                    continue;
+                }
+            },
+        };
+        match art.get_mut(name) {
+            Some(entry) if *entry.data == *data => {}
+            _ => art.insert(name, data.into_owned()),
         }
+    }
 
-        // skip symlinks etc.
-        if !file_type.is_file() {
-            continue;
+    Ok(true)
+}
+
+/// Names of the Art.dat entries at or below the changed paths, in the patched archive or added by
+/// one of the patches
+fn affected_entries(patches: &[PathBuf], changed: &Affected, art: &ArtArchive) -> anyhow::Result<Vec<String>> {
+    let roots = match changed {
+        Affected::Nothing => return Ok(Vec::new()),
+        Affected::Files(paths) => paths.iter().map(String::as_str).collect(),
+        Affected::All => vec!["assets"],
+    };
+    let is_affected = |name: &str| matches!(changed, Affected::All) || roots.iter()
+        .any(|root| name.strip_prefix(root).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')));
+
+    let mut names = art.entries.iter()
+        .map(|entry| entry.name.clone())
+        .filter(|name| is_affected(name))
+        .collect::<Vec<_>>();
+    let mut known = names.iter().cloned().collect::<HashSet<_>>();
+    for patch in patches {
+        for root in &roots {
+            let root = patch.join(root);
+            // skip patches without the file, e.g. without an assets directory at all
+            if !root.exists() {
+                continue;
+            }
+            for file in WalkDir::new(&root).sort_by_file_name() {
+                let file = file.map_err(|e| anyhow::anyhow!("Failed to walk directory: {}", e))?;
+                // skip directories, symlinks etc.
+                if !file.file_type().is_file() {
+                    continue;
+                }
+
+                let name = file.path().strip_prefix(patch)
+                    .context("Failed to strip prefix")?
+                    .to_str()
+                    .context("Failed to convert path to string")?
+                    .replace('\\', "/");
+                if known.insert(name.clone()) {
+                    names.push(name);
+                }
+            }
         }
+    }
+    Ok(names)
+}
 
-        // check if file exists in patch directory
-        let patch_file = patch.join(rel_path);
-        if !patch_file.exists() { // patch file doesn't exist, so copy over the original
-            copy_file(&file.path(), rel_path, &patched_assets)?;
+/// Applies the patches of a vanilla Art.dat entry in order
+fn patch_entry<'a>(patches: &[PathBuf], entry: &'a ArtEntry, report: &mut PatchReport) -> anyhow::Result<Cow<'a, [u8]>> {
+    let mut data = Cow::Borrowed(entry.data.as_slice());
+    let mut change = None;
+    for patch in patches {
+        let patch_file = patch.join(&entry.name);
+        if !patch_file.is_file() {
             continue;
         }
 
         let ext = patch_file.extension()
             .with_context(|| format!("Failed to get extension of {}", patch_file.display()))?;
 
-        // copy over the patch file if it's a png, csv or txt file
+        // replace the file if it's a png, csv or txt file
         // TODO: csv and txt patching
         if ext == OsStr::new("png") || ext == OsStr::new("csv") || ext == OsStr::new("txt") {
-            info!("Copying patch file for: {}", rel_path.display());
-            copy_file(&patch_file.as_path(), rel_path, &patched_assets)?;
+            info!("Copying patch file for: {}", entry.name);
+            data = Cow::Owned(std::fs::read(&patch_file)
+                .with_context(|| format!("Failed to read {}", patch_file.display()))?);
+            change = Some(&mut report.replaced);
         } else if ext == OsStr::new("xml") || ext == OsStr::new("fnt") {
-            info!("Patching xml file: {}", rel_path.display());
-            patch_xml(&file.path(), &patch_file, rel_path, &patched_assets)?;
+            info!("Patching xml file: {}", entry.name);
+            data = Cow::Owned(xml_patcher::patch(&entry.name, &data, &patch_file)?);
+            change = Some(&mut report.merged);
         } else {
             anyhow::bail!("Unsupported file type: {}", patch_file.display());
         }
     }
+    change.unwrap_or(&mut report.untouched)
+        .push(ReportedFile::new(&entry.name, &data));
+    Ok(data)
+}
 
-    // Loop over any files newly added with the patch
-    for file in WalkDir::new(patch) {
-        let file = file.map_err(|e| anyhow::anyhow!("Failed to walk directory: {}", e))?;
-        let rel_path = file.path().strip_prefix(patch)
-            .context("Failed to strip prefix")?;
-        if !rel_path.starts_with("assets") {
-            continue;
-        }
-
-        let target = patched_assets.join(rel_path);
-        let file_type = file.file_type();
-
-        // if file is a directory, just create it in the patched assets directory
-        if file_type.is_dir() && !target.exists() {
-            std::fs::create_dir_all(&target).context("Failed to create directory")?;
-            continue;
-        }
-
-        // skip symlinks etc.
-        if !file_type.is_file() {
-            continue;
-        }
-
-        // copy over the file if it doesn't exist already
-        if !target.exists() {
-            info!("Adding new file: {}", rel_path.display());
-            copy_file(&file.path(), rel_path, &patched_assets)?;
-        }
-    }
-
-    pack_to_assets(temp_dir, &game_dir, repack_info)?;
-
-    Ok(patched_assets)
+/// Reads a file added to Art.dat, a later patch replaces the file of an earlier one. Returns
+/// `None` if no patch has the file (anymore).
+fn new_entry(patches: &[PathBuf], name: &str, report: &mut PatchReport) -> anyhow::Result<Option<Cow<'static, [u8]>>> {
+    let Some(path) = patches.iter().rev().map(|patch| patch.join(name)).find(|path| path.is_file()) else {
+        return Ok(None);
+    };
+    info!("Adding new file: {}", name);
+    let data = std::fs::read(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    report.added.push(ReportedFile::new(name, &data));
+    Ok(Some(Cow::Owned(data)))
 }
 
-/// Copies a file from one of the input directories to the patched assets directory and makes sure
-/// the directory structure is created
-fn copy_file(file: &Path, rel_path: &Path, patched_assets: &PathBuf) -> anyhow::Result<()> {
-    let output = patched_assets.join(rel_path);
-    if let Some(parent) = output.parent() {
-        std::fs::create_dir_all(parent).context("Failed to create directory")?;
+/// Serializes the replaced objects with the given path ids again. Objects that aren't replaced
+/// anymore are dropped, so their original data is used again.
+pub fn serialize_objects(
+    repack: &RepackInfo,
+    path_ids: impl IntoIterator<Item = i64>,
+    serialized: &mut HashMap<i64, Vec<u8>>,
+) -> anyhow::Result<()> {
+    let endian = repack.assets.endian();
+    for path_id in path_ids {
+        let data = if let Some(audio) = repack.audio_assets.get(&path_id) {
+            unity::serialize_object(audio, endian, ())
+                .context("Failed to serialize audio object")?
+        } else if let Some(text_asset) = repack.text_assets.get(&path_id) {
+            unity::serialize_object(text_asset, endian, ())
+                .context("Failed to serialize text asset object")?
+        } else if let Some(texture) = repack.textures.get(&path_id) {
+            unity::serialize_object(texture, endian, (repack.unity_version,))
+                .context("Failed to serialize texture object")?
+        } else if let Some(sprite) = repack.sprites.get(&path_id) {
+            unity::serialize_object(sprite, endian, ())
+                .context("Failed to serialize sprite object")?
+        } else {
+            serialized.remove(&path_id);
+            continue;
+        };
+        serialized.insert(path_id, data);
     }
-    std::fs::copy(file, &output).context("Failed to copy file")?;
     Ok(())
 }
 
-/// Patches an XML file using the given patch file and writes the output to the patched assets directory
-fn patch_xml(original: &Path, patch_file: &PathBuf, rel_path: &Path, patched_assets: &PathBuf) -> anyhow::Result<()> {
-    let output = patched_assets.join(rel_path);
-    if let Some(parent) = output.parent() {
-        std::fs::create_dir_all(parent)
-            .context("Failed to create directory")?;
-    }
-    xml_patcher::patch(original, patch_file, &output)
+/// Where the data of an object is taken from when writing the repacked assets file.
+enum ObjectSource<'a> {
+    /// Copy the object data from the given offset in the original assets file
+    Original(u64),
+    /// The repacked Art.dat object
+    Art,
+    /// Already serialized object data of a replaced or newly added object
+    Data(&'a [u8]),
 }
 
-fn pack_to_assets(temp_dir: &PathBuf, game_dir: &PathBuf, repack: RepackInfo) -> anyhow::Result<()> {
+/// Writes the patched assets file, with the encrypted Art.dat, the serialized replaced objects
+/// and the new objects
+pub fn pack_to_assets(
+    game_dir: &Path,
+    repack: &RepackInfo,
+    art_data: &[u8],
+    replaced: &HashMap<i64, Vec<u8>>,
+    staging: &mut StagedWrites,
+) -> anyhow::Result<()> {
     let output = game_dir.join("sharedassets0.assets");
-    let patched = temp_dir.join("patched");
-    let temp_art = temp_dir.join("patched-art.dat");
-    pack::pack(&repack.art_key, &Some(patched.clone()), &temp_art)?;
-    let assets = repack.assets;
-    let new_art_len = std::fs::metadata(&temp_art)
-        .context("Failed to get metadata of temp art file")?
-        .len();
-
-    // header
-    let mut header = AssetsFileHeader { file_size: 0, ..assets.header };
+    let assets = &repack.assets;
+    let new_art_len = art_data.len() as u64;
+    let original_metadata_len = metadata_len(assets)?;
 
     // content
     let mut objects = Vec::new();
-    let mut current_offset = 0;
+    let mut sources = Vec::new();
     for obj in &assets.content.objects {
-        let mut new_object = ObjectInfo {
+        let source = if obj.path_id == repack.art_path_id {
+            ObjectSource::Art
+        } else if let Some(data) = replaced.get(&obj.path_id) {
+            ObjectSource::Data(data)
+        } else {
+            ObjectSource::Original(obj.byte_start)
+        };
+        objects.push(ObjectInfo {
             path_id: obj.path_id,
-            byte_start: current_offset,
-            byte_size: 0,
+            byte_start: 0,
+            byte_size: obj.byte_size,
             type_id: obj.type_id,
+        });
+        sources.push(source);
+    }
+
+    // append new objects, registering their types if needed
+    let mut content = assets.content.clone();
+    for new_obj in &repack.new_objects {
+        let type_id = content.type_index(new_obj.class_id);
+        objects.push(ObjectInfo {
+            path_id: new_obj.path_id,
+            byte_start: 0,
+            byte_size: 0,
+            type_id,
+        });
+        sources.push(ObjectSource::Data(&new_obj.data));
+    }
+
+    let mut current_offset = 0;
+    for (obj, source) in objects.iter_mut().zip(&sources) {
+        obj.byte_start = current_offset;
+        obj.byte_size = match source {
+            ObjectSource::Art => (new_art_len + TextAsset::header_len(ART_OBJECT_NAME)) as u32,
+            ObjectSource::Data(data) => data.len() as u32,
+            ObjectSource::Original(_) => obj.byte_size,
         };
-        if obj.path_id == repack.art_path_id {
-            new_object.byte_size = (new_art_len + ART_OBJ_HEADER_LEN) as u32;
-        } else {
-            new_object.byte_size = obj.byte_size;
-        }
-        current_offset += new_object.byte_size as u64;
+        current_offset += obj.byte_size as u64;
 
         // When writing the object data, the start of object data is always aligned to 8 bytes, and
         // the end of object data is always aligned to 4 bytes. The bytes used to pad the end of the
@@ -170,62 +256,288 @@ fn pack_to_assets(temp_dir: &PathBuf, game_dir: &PathBuf, repack: RepackInfo) ->
         // used to align the start of the object data to 8 bytes are not included in any size.
         if current_offset % 8 != 0 {
             let padding = 8 - (current_offset % 8);
-            new_object.byte_size += (padding % 4) as u32;
+            obj.byte_size += (padding % 4) as u32;
             current_offset += padding;
         }
+    }
 
-        objects.push(new_object);
+    // header
+    let header = AssetsFileHeader { file_size: 0, ..assets.header.clone() };
+    let content = AssetsFileContent { objects, ..content };
+    let mut new_assets = AssetsFile { header, content };
+
+    // New objects and types grow the metadata. If it doesn't fit in front of the object data
+    // anymore, the object data has to be moved back.
+    let new_metadata_len = metadata_len(&new_assets)?;
+    let header = &mut new_assets.header;
+    header.metadata_size = header.metadata_size + new_metadata_len - original_metadata_len;
+    if new_metadata_len > header.offset_first_file {
+        header.offset_first_file = new_metadata_len.div_ceil(16) * 16;
     }
     header.file_size = header.offset_first_file + current_offset;
-    let content = AssetsFileContent { objects, ..assets.content };
-    let new_assets = AssetsFile { header, content };
 
-    let mut writer = BufWriter::new(File::create(&output)
+    let mut writer = BufWriter::new(staging.create(&output)
         .context("Failed to create output file")?);
     new_assets.write(&mut writer)
         .context("Failed to write assets file header")?;
 
     // pad with zeroes until first file offset is reached (yes this is also what Unity does)
-    let pad = assets.header.offset_first_file - writer.stream_position()
+    let file_offset = new_assets.header.offset_first_file;
+    let pad = file_offset - writer.stream_position()
         .context("Failed to get current position in output file")?;
     write_zeroes(&mut writer, pad)?;
 
     // write the actual object data
     let mut original = BufReader::new(File::open(&repack.original_assets)
         .context("Failed to open original assets file")?);
-    let original_file_offset = &assets.header.offset_first_file;
-    for (obj, old_obj) in new_assets.content.objects.iter().zip(assets.content.objects) {
+    let original_file_offset = assets.header.offset_first_file;
+    for (obj, source) in new_assets.content.objects.iter().zip(sources) {
         let pos = writer.stream_position()
             .context("Failed to get current position in output file")?;
-        if pos != obj.byte_start + original_file_offset {
+        if pos != obj.byte_start + file_offset {
             // pad with zeroes until the object's start offset is reached
-            let pad = obj.byte_start + original_file_offset - pos;
+            let pad = obj.byte_start + file_offset - pos;
             write_zeroes(&mut writer, pad).context("Failed to write padding zeroes")?;
         }
 
-        if obj.path_id == repack.art_path_id {
-            AlignedString("Art.dat".to_string()).write_options(&mut writer, new_assets.endian(), AlignmentArgs::new(4))
-                .context("Failed to write object name")?;
-            (new_art_len as u32).write_options(&mut writer, new_assets.endian(), ())
-                .context("Failed to write object data length")?;
-            // copy over the new art file
-            let mut art_file = BufReader::new(File::open(&temp_art)
-                .context("Failed to open temp art file")?);
-            std::io::copy(&mut art_file, &mut writer)
-                .context("Failed to copy new art file to assets file")?;
-        } else if let Some(audio) = repack.audio_assets.get(&obj.path_id) {
-            audio.write_options(&mut writer, new_assets.endian(), ())
-                .context("Failed to write audio object")?;
-        } else {
-            original.seek(SeekFrom::Start(original_file_offset + old_obj.byte_start))
-                .context("Failed to seek to object in original assets file")?;
-            let mut data = vec![0; obj.byte_size as usize];
-            original.read_exact(&mut data)
-                .context("Failed to read object data from original assets file")?;
-            writer.write_all(&data)?;
+        match source {
+            ObjectSource::Art => {
+                AlignedString(ART_OBJECT_NAME.to_string()).write_options(&mut writer, new_assets.endian(), AlignmentArgs::new(4))
+                    .context("Failed to write object name")?;
+                (new_art_len as u32).write_options(&mut writer, new_assets.endian(), ())
+                    .context("Failed to write object data length")?;
+                writer.write_all(art_data)
+                    .context("Failed to write new art file to assets file")?;
+            }
+            ObjectSource::Data(data) => {
+                writer.write_all(data)
+                    .context("Failed to write object data")?;
+            }
+            ObjectSource::Original(byte_start) => {
+                original.seek(SeekFrom::Start(original_file_offset + byte_start))
+                    .context("Failed to seek to object in original assets file")?;
+                let mut data = vec![0; obj.byte_size as usize];
+                original.read_exact(&mut data)
+                    .context("Failed to read object data from original assets file")?;
+                writer.write_all(&data)?;
+            }
         }
     }
 
+    // the file size in the header includes the alignment after the last object
+    let pos = writer.stream_position()
+        .context("Failed to get current position in output file")?;
+    write_zeroes(&mut writer, new_assets.header.file_size - pos)
+        .context("Failed to write padding zeroes")?;
+
+    writer.flush().context("Failed to write assets file")?;
     info!("Packed {} objects", new_assets.content.objects.len());
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Returns the length of the header and metadata of an assets file when serialized.
+fn metadata_len(assets: &AssetsFile) -> anyhow::Result<u64> {
+    let mut writer = Cursor::new(Vec::new());
+    assets.write(&mut writer)
+        .context("Failed to serialize assets file metadata")?;
+    Ok(writer.position())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use binrw::{BinRead, NullString};
+
+    use super::*;
+    use crate::unity::{NewObject, SerializedType};
+    use crate::unity::util::{Endian, U8Bool, UnityVersion};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("papers-tools-{}-{}", name, rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn serialized_type(class_id: i32) -> SerializedType {
+        SerializedType {
+            class_id,
+            is_stripped_type: U8Bool(false),
+            script_type_index: u16::MAX,
+            script_id: None,
+            old_type_hash: [0; 16],
+        }
+    }
+
+    /// Writes a little endian assets file with the given objects (type, data) and returns it
+    fn write_assets(path: &Path, objects: &[(i32, Vec<u8>)]) -> AssetsFile {
+        let mut assets = AssetsFile {
+            header: AssetsFileHeader {
+                version: 22,
+                metadata_size: 0,
+                file_size: 0,
+                offset_first_file: 0,
+                endianness: Endian::Little,
+            },
+            content: AssetsFileContent {
+                unity_version: NullString::from("2019.4.0f1"),
+                target: 5,
+                enable_type_tree: U8Bool(false),
+                types: vec![serialized_type(unity::TEXT_ASSET_CLASS), serialized_type(unity::TEXTURE_2D_CLASS)],
+                objects: Vec::new(),
+                script_types: Vec::new(),
+                externals: Vec::new(),
+                ref_types: Vec::new(),
+                user_information: NullString::default(),
+            },
+        };
+        let mut offset = 0;
+        for (path_id, (type_id, data)) in objects.iter().enumerate() {
+            assets.content.objects.push(ObjectInfo {
+                path_id: path_id as i64 + 1,
+                byte_start: offset,
+                byte_size: data.len() as u32,
+                type_id: *type_id,
+            });
+            offset = (offset + data.len() as u64).next_multiple_of(8);
+        }
+        let metadata_len = metadata_len(&assets).unwrap();
+        // leave no room for more metadata, so growing it has to move the object data
+        assets.header.offset_first_file = metadata_len.next_multiple_of(16);
+        assets.header.metadata_size = metadata_len - 48;
+        assets.header.file_size = assets.header.offset_first_file + offset;
+
+        let mut file = Cursor::new(Vec::new());
+        assets.write(&mut file).unwrap();
+        let mut file = file.into_inner();
+        for (obj, (_, data)) in assets.content.objects.iter().zip(objects) {
+            file.resize((assets.header.offset_first_file + obj.byte_start) as usize, 0);
+            file.extend_from_slice(data);
+        }
+        fs::write(path, file).unwrap();
+        assets
+    }
+
+    fn object_data<'a>(file: &'a [u8], assets: &AssetsFile, obj: &ObjectInfo) -> &'a [u8] {
+        let start = (assets.header.offset_first_file + obj.byte_start) as usize;
+        &file[start..start + obj.byte_size as usize]
+    }
+
+    fn archive(entries: &[(&str, &[u8])]) -> ArtArchive {
+        ArtArchive {
+            entries: entries.iter()
+                .map(|(name, data)| ArtEntry { name: name.to_string(), data: data.to_vec() })
+                .collect(),
+        }
+    }
+
+    fn changed(paths: &[&str]) -> Affected {
+        Affected::Files(paths.iter().map(|path| path.to_string()).collect::<BTreeSet<_>>())
+    }
+
+    fn data<'a>(art: &'a ArtArchive, name: &str) -> Option<&'a [u8]> {
+        art.get(name).map(|entry| entry.data.as_slice())
+    }
+
+    #[test]
+    fn reapplies_only_changed_entries() {
+        let patch = std::env::temp_dir().join(format!("papers-tools-assets-{}", rand::random::<u64>()));
+        fs::create_dir_all(patch.join("assets/images")).unwrap();
+        fs::write(patch.join("assets/images/desk.png"), b"patched desk").unwrap();
+        fs::write(patch.join("assets/images/new.png"), b"new").unwrap();
+        let patches = [patch.clone()];
+        let vanilla = archive(&[("assets/images/desk.png", b"desk"), ("assets/images/booth.png", b"booth")]);
+
+        let mut art = vanilla.clone();
+        let mut report = PatchReport::default();
+        assert!(patch_assets(&patches, &Affected::All, &vanilla, &mut art, &mut report).unwrap());
+        assert_eq!(data(&art, "assets/images/desk.png"), Some(&b"patched desk"[..]));
+        assert_eq!(data(&art, "assets/images/new.png"), Some(&b"new"[..]));
+        assert_eq!(report.replaced.len(), 1);
+        assert_eq!(report.added.len(), 1);
+        assert_eq!(report.untouched.len(), 1);
+
+        // removing the files restores the vanilla entry and drops the added one
+        fs::remove_file(patch.join("assets/images/desk.png")).unwrap();
+        fs::remove_file(patch.join("assets/images/new.png")).unwrap();
+        let changes = changed(&["assets/images/desk.png", "assets/images/new.png"]);
+        assert!(patch_assets(&patches, &changes, &vanilla, &mut art, &mut report).unwrap());
+        assert_eq!(data(&art, "assets/images/desk.png"), Some(&b"desk"[..]));
+        assert_eq!(data(&art, "assets/images/new.png"), None);
+        assert!(report.replaced.is_empty());
+        assert!(report.added.is_empty());
+        assert_eq!(report.untouched.len(), 2);
+
+        // a changed directory affects everything below it, but nothing else
+        fs::write(patch.join("assets/images/booth.png"), b"patched booth").unwrap();
+        assert!(patch_assets(&patches, &changed(&["assets/images"]), &vanilla, &mut art, &mut report).unwrap());
+        assert_eq!(data(&art, "assets/images/booth.png"), Some(&b"patched booth"[..]));
+        assert!(!patch_assets(&patches, &changed(&["assets/sounds"]), &vanilla, &mut art, &mut report).unwrap());
+
+        fs::remove_dir_all(&patch).unwrap();
+    }
+
+    #[test]
+    fn pack_recomputes_offsets_and_metadata() {
+        let dir = temp_dir("pack");
+        let original_path = dir.join("original.assets");
+        let art_object = TextAsset { object_name: AlignedString(ART_OBJECT_NAME.to_string()), script: vec![0xAA; 20] };
+        let art_object = unity::serialize_object(&art_object, binrw::Endian::Little, ()).unwrap();
+        let original = write_assets(&original_path, &[(0, vec![1; 12]), (0, art_object), (1, vec![3; 8])]);
+
+        let repack = RepackInfo {
+            assets: original.clone(),
+            audio_assets: HashMap::new(),
+            text_asset_names: HashMap::new(),
+            text_assets: HashMap::new(),
+            texture_names: HashMap::new(),
+            textures: HashMap::new(),
+            sprite_names: HashMap::new(),
+            sprites: HashMap::new(),
+            unity_version: UnityVersion(2019, 4),
+            art_key: String::new(),
+            art: ArtArchive::default(),
+            art_path_id: 2,
+            original_assets: original_path,
+            // a new class grows the metadata
+            new_objects: vec![NewObject { path_id: 4, class_id: unity::AUDIO_CLIP_CLASS, name: "clip".to_string(), data: vec![4; 4] }],
+        };
+        let art_data = vec![0xBB; 37];
+        let replaced = HashMap::from([(3, vec![5; 20])]);
+        let output = dir.join("output");
+        let mut staging = StagedWrites::redirected(&dir, &output);
+        pack_to_assets(&dir, &repack, &art_data, &replaced, &mut staging).unwrap();
+
+        let file = fs::read(output.join("sharedassets0.assets")).unwrap();
+        let packed = AssetsFile::read(&mut Cursor::new(&file)).unwrap();
+        let header = &packed.header;
+        assert_eq!(header.file_size, file.len() as u64);
+        let packed_metadata_len = metadata_len(&packed).unwrap();
+        assert_eq!(header.metadata_size - original.header.metadata_size, packed_metadata_len - metadata_len(&original).unwrap());
+        assert!(header.offset_first_file >= packed_metadata_len);
+        assert!(header.offset_first_file > original.header.offset_first_file);
+        assert_eq!(header.offset_first_file % 16, 0);
+        assert_eq!(packed.content.types[2].class_id, unity::AUDIO_CLIP_CLASS);
+
+        let objects = &packed.content.objects;
+        assert_eq!(objects.iter().map(|obj| obj.path_id).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(objects[3].type_id, 2);
+        for obj in objects {
+            assert_eq!(obj.byte_start % 8, 0, "object {} isn't aligned", obj.path_id);
+        }
+        for pair in objects.windows(2) {
+            assert!(pair[0].byte_start + pair[0].byte_size as u64 <= pair[1].byte_start);
+        }
+
+        assert_eq!(object_data(&file, &packed, &objects[0]), [1; 12]);
+        let art = TextAsset::read_le(&mut Cursor::new(object_data(&file, &packed, &objects[1]))).unwrap();
+        assert_eq!(*art.object_name, ART_OBJECT_NAME);
+        assert_eq!(art.script, art_data);
+        assert_eq!(objects[1].byte_size as u64 % 4, 0);
+        assert_eq!(object_data(&file, &packed, &objects[2]), [5; 20]);
+        assert_eq!(object_data(&file, &packed, &objects[3]), [4; 4]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,105 +1,342 @@
-use std::{fs, io};
+use std::fs;
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::fmt::Debug;
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use binrw::io::BufReader;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
+use crate::command::patch::report::{ObjectClass, PatchReport};
+use crate::command::patch::staging::StagedWrites;
 use crate::command::unpack::RepackInfo;
+use crate::fsb::{Fsb5, FsbCodec};
+use crate::fsb::wav::wav_to_fsb;
+use crate::unity;
 use crate::unity::audio::{AudioClip, AudioCompressionFormat, StreamedResource};
 use crate::unity::util::{AlignedString, U8Bool};
 
 type AudioPatchList = Vec<AudioPatch>;
 
-/// If you rename this for some reason, make sure it has a length between 21 and 24 characters
-/// (inclusive) to avoid changing the size of the audio clips in the assets file.
-const MODDED_RESOURCES_FILE: &str = "modded_assets0.resource";
+/// Default name of the resource file the patched audio is written to
+pub const DEFAULT_RESOURCE_NAME: &str = "modded_assets0.resource";
 
+/// Audio clip replacement. Fields left out are read from the FSB file, or taken from the
+/// original clip (or Unity's defaults for new clips).
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AudioPatch {
     pub object_name: String,
     pub patched_path: PathBuf,
-    pub load_type: i32,
-    pub channels: i32,
-    pub frequency: i32,
-    pub bits_per_sample: i32,
-    pub length: f32,
-    pub is_tracker_format: bool,
-    pub subsound_index: i32,
-    pub preload_audio_data: bool,
-    pub load_in_background: bool,
-    pub legacy3d: bool,
-    pub compression_format: AudioCompressionFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_type: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bits_per_sample: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_tracker_format: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsound_index: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preload_audio_data: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_in_background: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy3d: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_format: Option<AudioCompressionFormat>,
+    /// Adds a new AudioClip with the given object name instead of replacing an existing one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub create: bool,
 }
 
-pub fn patch_audio(audio_patches_path: &PathBuf, game_dir: &PathBuf, repack_info: &mut RepackInfo) -> anyhow::Result<()> {
-    let audio_patches = fs::read_to_string(audio_patches_path)
-        .context("Failed to read audio patches file")?;
-    let audio_patches: AudioPatchList = serde_json::from_str(&audio_patches)
-        .context("Failed to parse audio patches file")?;
+/// Writes the audio of all patches into the resource file `resource_name` in the game directory and
+/// points the patched clips to it. The audio patch lists are combined in load order.
+/// `resource_name` has to be validated with [check_resource_name] up front.
+pub fn patch_audio(
+    audio_patches_paths: &[PathBuf],
+    game_dir: &Path,
+    resource_name: &str,
+    repack_info: &mut RepackInfo,
+    staging: &mut StagedWrites,
+    report: &mut PatchReport,
+) -> anyhow::Result<()> {
+    let audio_patches = read_audio_patches(audio_patches_paths)?;
 
     let by_object_name = repack_info.audio_assets.iter()
         .map(|(path_id, clip)| (clip.object_name.as_str(), (path_id.clone(), clip)))
         .collect::<HashMap<_, _>>();
 
     let mut patched_clips: HashMap<i64, AudioClip> = HashMap::new();
-    let mut modded_audio_writer = BufWriter::new(File::create(
-        game_dir.join(MODDED_RESOURCES_FILE)
-    ).context("Failed to create modded audio file")?);
-    let patches_dir = audio_patches_path.parent()
-        .context("Failed to get parent directory of audio patches file")?;
+    let mut new_clips: Vec<AudioClip> = Vec::new();
+    let mut modded_audio_writer = BufWriter::new(staging.create(&game_dir.join(resource_name))
+        .context("Failed to create modded audio file")?);
 
     let mut offset = 0u64;
     info!("Patching {} audio clips...", audio_patches.len());
-    for patch in &audio_patches {
-        if patch.patched_path.extension() != Some(OsStr::new("fsb")) {
-            anyhow::bail!("Only FSB files are supported for audio patches.");
-        }
-
-        if let Some((path_id, clip)) = by_object_name.get(patch.object_name.as_str()) {
-            let mut reader = BufReader::new(File::open(patches_dir.join(&patch.patched_path))
-                .context("Failed to open patched audio file")?);
-            let written = io::copy(&mut reader, &mut modded_audio_writer)
-                .context("Failed to copy patched audio file to modded audio file")?;
-
-            let new_clip = AudioClip {
-                object_name: clip.object_name.clone(),
-                resource: StreamedResource {
-                    source: AlignedString(MODDED_RESOURCES_FILE.to_string()),
-                    offset: offset as i64,
-                    size: written as i64,
-                },
-                load_type: patch.load_type,
-                channels: patch.channels,
-                frequency: patch.frequency,
-                bits_per_sample: patch.bits_per_sample,
-                length: patch.length,
-                is_tracker_format: U8Bool(patch.is_tracker_format),
-                subsound_index: patch.subsound_index,
-                preload_audio_data: U8Bool(patch.preload_audio_data),
-                load_in_background: U8Bool(patch.load_in_background),
-                legacy3d: U8Bool(patch.legacy3d),
-                compression_format: patch.compression_format.clone(),
-            };
-            patched_clips.insert(*path_id, new_clip);
-
-            offset += written;
-        } else {
+    for (patch, patches_dir) in &audio_patches {
+        let existing = by_object_name.get(patch.object_name.as_str());
+        if existing.is_none() && !patch.create {
             let mut available = by_object_name.keys().map(|s| s.to_string()).collect::<Vec<_>>();
             available.sort();
             anyhow::bail!("Audio name {} in audio patches does not exist in the assets file. Available audio names:\n{}",
                 patch.object_name,
                 available.join(", ")
             );
+        } else if existing.is_some() && patch.create {
+            anyhow::bail!("Audio name {} in audio patches is marked to be created, but already exists in the assets file",
+                patch.object_name
+            );
+        }
+
+        let data = read_audio_file(&patches_dir.join(&patch.patched_path))
+            .with_context(|| format!("Failed to read audio file {} of audio patch {}",
+                patch.patched_path.display(), patch.object_name))?;
+        let fsb = read_fsb(&mut Cursor::new(&data))
+            .with_context(|| format!("Invalid FSB file {} in audio patch {}",
+                patch.patched_path.display(), patch.object_name))?;
+        modded_audio_writer.write_all(&data)
+            .context("Failed to copy patched audio file to modded audio file")?;
+        let written = data.len() as u64;
+
+        let resource = StreamedResource {
+            source: AlignedString(resource_name.to_string()),
+            offset: offset as i64,
+            size: written as i64,
+        };
+        let new_clip = build_clip(patch, &fsb, existing.map(|(_, clip)| *clip), resource)?;
+
+        report.object(ObjectClass::AudioClip, &patch.object_name, existing.is_none(), Some(&data));
+        if let Some((path_id, _)) = existing {
+            patched_clips.insert(*path_id, new_clip);
+        } else {
+            new_clips.push(new_clip);
         }
+
+        offset += written;
+    }
+
+    modded_audio_writer.flush()
+        .context("Failed to write modded audio file")?;
+
+    for clip in new_clips {
+        let data = unity::serialize_object(&clip, repack_info.assets.endian(), ())
+            .context("Failed to serialize new audio object")?;
+        let path_id = repack_info.add_object(unity::AUDIO_CLIP_CLASS, &clip.object_name, data);
+        info!("Added new audio clip {} with path id {}", clip.object_name, path_id);
     }
 
     repack_info.audio_assets = patched_clips;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Reads the audio patch lists, together with the directory their audio files are relative to. A
+/// patch for the same clip as an earlier list replaces the earlier one.
+fn read_audio_patches(paths: &[PathBuf]) -> anyhow::Result<Vec<(AudioPatch, PathBuf)>> {
+    let mut combined: Vec<(AudioPatch, PathBuf)> = Vec::new();
+    for path in paths {
+        let audio_patches = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read audio patches file {}", path.display()))?;
+        let audio_patches: AudioPatchList = serde_json::from_str(&audio_patches)
+            .with_context(|| format!("Failed to parse audio patches file {}", path.display()))?;
+        let dir = path.parent()
+            .context("Failed to get parent directory of audio patches file")?
+            .to_path_buf();
+
+        for mut patch in audio_patches {
+            match combined.iter_mut().find(|(existing, _)| existing.object_name == patch.object_name) {
+                Some(existing) => {
+                    // a clip added by an earlier patch still has to be created
+                    patch.create |= existing.0.create;
+                    *existing = (patch, dir.clone());
+                }
+                None => combined.push((patch, dir.clone())),
+            }
+        }
+    }
+    Ok(combined)
+}
+
+/// Makes sure the resource file stays in the data folder
+pub fn check_resource_name(resource_name: &str) -> anyhow::Result<()> {
+    if resource_name.is_empty() || Path::new(resource_name).file_name() != Some(OsStr::new(resource_name)) {
+        anyhow::bail!("Invalid audio resource name {:?}, it must be a plain file name", resource_name);
+    }
+    Ok(())
+}
+
+/// Reads an audio file as an FSB sound bank, converting WAV files on the fly
+fn read_audio_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("fsb") => Ok(fs::read(path)?),
+        Some("wav") => wav_to_fsb(BufReader::new(File::open(path)?)),
+        _ => anyhow::bail!("Only FSB and WAV files are supported"),
+    }
+}
+
+/// Reads and validates the headers of an FSB file
+fn read_fsb<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Fsb5> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let fsb = Fsb5::read(reader)?;
+    fsb.validate(file_len)?;
+    Ok(fsb)
+}
+
+/// Builds the clip for an audio patch. Format fields are read from the FSB file, everything else
+/// falls back to the original clip, or the values Unity uses for newly imported clips.
+fn build_clip(
+    patch: &AudioPatch,
+    fsb: &Fsb5,
+    original: Option<&AudioClip>,
+    resource: StreamedResource,
+) -> anyhow::Result<AudioClip> {
+    let subsound_index = patch.subsound_index
+        .or(original.map(|clip| clip.subsound_index))
+        .unwrap_or(0);
+    let sample = usize::try_from(subsound_index).ok()
+        .and_then(|index| fsb.samples.get(index))
+        .with_context(|| format!("Audio patch {}: FSB file has no subsound {}", patch.object_name, subsound_index))?;
+
+    let compression_format = match (compression_format(fsb.header.codec), &patch.compression_format) {
+        (Some(format), user) => checked(patch, "compressionFormat", user.clone(), format),
+        (None, Some(format)) => format.clone(),
+        (None, None) => anyhow::bail!(
+            "Audio patch {}: FSB codec {:?} has no matching compression format, please specify compressionFormat",
+            patch.object_name, fsb.header.codec
+        ),
+    };
+
+    let length = sample.length();
+    let length = match patch.length {
+        Some(user) if (user - length).abs() > 0.01 => {
+            warn!("Audio patch {}: length is {} but the FSB file says {}, using the value from the file",
+                patch.object_name, user, length);
+            length
+        }
+        _ => length,
+    };
+
+    Ok(AudioClip {
+        object_name: AlignedString(patch.object_name.clone()),
+        load_type: patch.load_type
+            .or(original.map(|clip| clip.load_type))
+            .unwrap_or(0),
+        channels: checked(patch, "channels", patch.channels, sample.channels as i32),
+        frequency: checked(patch, "frequency", patch.frequency, sample.frequency as i32),
+        bits_per_sample: checked(patch, "bitsPerSample", patch.bits_per_sample, fsb.header.codec.bits_per_sample()),
+        length,
+        is_tracker_format: U8Bool(patch.is_tracker_format
+            .or(original.map(|clip| *clip.is_tracker_format))
+            .unwrap_or(false)),
+        subsound_index,
+        preload_audio_data: U8Bool(patch.preload_audio_data
+            .or(original.map(|clip| *clip.preload_audio_data))
+            .unwrap_or(true)),
+        load_in_background: U8Bool(patch.load_in_background
+            .or(original.map(|clip| *clip.load_in_background))
+            .unwrap_or(false)),
+        legacy3d: U8Bool(patch.legacy3d
+            .or(original.map(|clip| *clip.legacy3d))
+            .unwrap_or(true)),
+        resource,
+        compression_format,
+    })
+}
+
+/// Returns the value read from the FSB file, warning if the patch specifies a different one
+fn checked<T: PartialEq + Debug>(patch: &AudioPatch, field: &str, user: Option<T>, file: T) -> T {
+    if let Some(user) = user {
+        if user != file {
+            warn!("Audio patch {}: {} is {:?} but the FSB file says {:?}, using the value from the file",
+                patch.object_name, field, user, file);
+        }
+    }
+    file
+}
+
+fn compression_format(codec: FsbCodec) -> Option<AudioCompressionFormat> {
+    Some(match codec {
+        FsbCodec::Pcm8 | FsbCodec::Pcm16 | FsbCodec::Pcm24 | FsbCodec::Pcm32 | FsbCodec::PcmFloat => AudioCompressionFormat::PCM,
+        FsbCodec::ImaAdpcm | FsbCodec::FAdpcm => AudioCompressionFormat::ADPCM,
+        FsbCodec::Vorbis => AudioCompressionFormat::Vorbis,
+        FsbCodec::Mpeg => AudioCompressionFormat::MP3,
+        FsbCodec::GcAdpcm => AudioCompressionFormat::GCADPCM,
+        FsbCodec::Vag => AudioCompressionFormat::PSMVAG,
+        FsbCodec::HeVag => AudioCompressionFormat::HEVAG,
+        FsbCodec::Xma => AudioCompressionFormat::XMA,
+        FsbCodec::At9 => AudioCompressionFormat::ATRAC9,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_patch_list(name: &str, json: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("papers-tools-{}-{}", name, rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audio_patches.json");
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    #[test]
+    fn later_audio_patches_win() {
+        let base = write_patch_list("base", r#"[
+            {"objectName": "stamp", "patchedPath": "stamp.fsb"},
+            {"objectName": "siren", "patchedPath": "siren.fsb", "create": true},
+            {"objectName": "music", "patchedPath": "music.fsb"}
+        ]"#);
+        let addon = write_patch_list("addon", r#"[
+            {"objectName": "stamp", "patchedPath": "louder.wav", "channels": 2},
+            {"objectName": "siren", "patchedPath": "siren2.fsb"},
+            {"objectName": "door", "patchedPath": "door.fsb"}
+        ]"#);
+        let base_dir = base.parent().unwrap().to_path_buf();
+        let addon_dir = addon.parent().unwrap().to_path_buf();
+
+        let patches = read_audio_patches(&[base, addon]).unwrap();
+        let summary = patches.iter()
+            .map(|(patch, dir)| (patch.object_name.as_str(), patch.patched_path.to_str().unwrap(), patch.create, dir))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            ("stamp", "louder.wav", false, &addon_dir),
+            // a clip created by an earlier patch still has to be created
+            ("siren", "siren2.fsb", true, &addon_dir),
+            ("music", "music.fsb", false, &base_dir),
+            ("door", "door.fsb", false, &addon_dir),
+        ]);
+        // the later entry replaces the earlier one as a whole
+        assert_eq!(patches[0].0.channels, Some(2));
+
+        fs::remove_dir_all(base_dir).unwrap();
+        fs::remove_dir_all(addon_dir).unwrap();
+    }
+
+    #[test]
+    fn reports_invalid_patch_lists() {
+        let path = write_patch_list("invalid", r#"[{"objectName": "stamp"}]"#);
+        let error = read_audio_patches(std::slice::from_ref(&path)).unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to parse audio patches file"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn resource_name_must_be_a_file_name() {
+        assert!(check_resource_name(DEFAULT_RESOURCE_NAME).is_ok());
+        assert!(check_resource_name("").is_err());
+        assert!(check_resource_name("../modded.resource").is_err());
+        assert!(check_resource_name("sub/modded.resource").is_err());
+    }
+}
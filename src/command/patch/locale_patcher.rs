@@ -1,48 +1,57 @@
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
 use anyhow::Context;
 use binrw::io::BufReader;
 use tracing::info;
+use crate::command::art::ArtArchive;
+use crate::command::patch::report::{PatchReport, ReportedFile};
+use crate::command::patch::staging::StagedWrites;
 use zip::{CompressionMethod, ZipArchive};
 use zip::write::{ExtendedFileOptions, FileOptions};
 
-pub fn patch_locale(patched: &PathBuf, game_dir: &PathBuf) -> anyhow::Result<()> {
-    let patched = patched.join("assets");
+/// Rewrites en.zip from `original`, replacing the entries that are also part of Art.dat with
+/// their patched versions
+pub fn patch_locale(
+    art: &ArtArchive,
+    original: &Path,
+    game_dir: &Path,
+    staging: &mut StagedWrites,
+    report: &mut PatchReport,
+) -> anyhow::Result<()> {
     info!("Patching en.zip...");
 
-    let input = BufReader::new(File::open(game_dir.join("StreamingAssets/loc/en.zip-bak"))
-        .context("Failed to open en.zip-bak")?
+    let input = BufReader::new(File::open(original)
+        .with_context(|| format!("Failed to open {}", original.display()))?
     );
-    let output = BufWriter::new(File::create(game_dir.join("StreamingAssets/loc/en.zip"))
+    let output = BufWriter::new(staging.create(&game_dir.join("StreamingAssets/loc/en.zip"))
         .context("Failed to create en.zip")?
     );
 
     let mut zip = ZipArchive::new(input)
-        .context("Failed to read en.zip-bak")?;
+        .with_context(|| format!("Failed to read {}", original.display()))?;
     let mut writer = zip::ZipWriter::new(output);
 
     for i in 0..zip.len() {
         let entry = zip.by_index(i).context("Failed to read entry")?;
         let name = entry.name();
-        let patch_file = patched.join(&name[1..]); // Remove leading slash
+        let art_entry = art.get(&format!("assets/{}", &name[1..])); // Remove leading slash
 
-        if patch_file.exists() {
+        if let Some(art_entry) = art_entry {
             // General assets, just copy the patched file
             writer.start_file::<&str, ExtendedFileOptions>(name, FileOptions::default().compression_method(CompressionMethod::Stored))
                 .context("Failed to start file")?;
-            let mut patch_file = BufReader::new(File::open(patch_file)
-                .context("Failed to open patch file")?
-            );
-            std::io::copy(&mut patch_file, &mut writer).context("Failed to copy patch file")?;
+            writer.write_all(&art_entry.data).context("Failed to copy patch file")?;
+            report.locale.push(ReportedFile::new(name, &art_entry.data));
         } else {
             // Locale specific assets, just copy the original file
             writer.raw_copy_file(entry).context("Failed to copy entry")?;
         }
     }
 
-    writer.finish().context("Failed to finish writing")?;
+    writer.finish().context("Failed to finish writing")?
+        .flush().context("Failed to write en.zip")?;
 
     info!("Patched en.zip locale with {} entries", zip.len());
 
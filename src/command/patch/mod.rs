@@ -1,103 +1,487 @@
-use std::env::temp_dir;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
-use rand::random;
-use tracing::info;
-use unpack::unpack_assets;
+use tracing::{error, info, warn};
+use unpack::{AssetsUnpackOptions, RepackInfo, unpack_assets};
+use walkdir::WalkDir;
 
-use crate::{I18nCompatMode, Args};
-use crate::command::patch::assets_patcher::patch_assets;
-use crate::command::patch::audio_patcher::patch_audio;
+use crate::{I18nCompatMode, Args, unity};
+use crate::command::patch::assets_patcher::{pack_to_assets, patch_assets, serialize_objects};
+use crate::command::patch::audio_patcher::{check_resource_name, patch_audio};
 use crate::command::patch::locale_patcher::patch_locale;
-use crate::command::{DATA_FOLDER_NAME, unpack};
+use crate::command::patch::report::{ObjectClass, PatchReport, ReportedFile};
+use crate::command::patch::sprite_patcher::patch_sprites;
+use crate::command::patch::staging::StagedWrites;
+use crate::command::patch::text_asset_patcher::patch_text_assets;
+use crate::command::patch::texture_patcher::patch_textures;
+use crate::command::patch::watch::{Affected, Changes};
+use crate::command::{ASSETS_FILE_NAME, DATA_FOLDER_NAME, LOCALE_FILE_NAME, unpack};
+use crate::command::backup::{BackupRecords, GameBuild};
+use crate::command::manifest::{FileChange, hash_file, InstallManifest};
+use crate::command::workspace::Workspace;
 
 mod assets_patcher;
 mod xml_patcher;
 mod locale_patcher;
+mod report;
+mod sprite_patcher;
+mod staging;
+mod text_asset_patcher;
+mod texture_patcher;
+mod watch;
 pub mod audio_patcher;
 
-pub fn patch(args: &Args, patch: &PathBuf, locale_mode: &I18nCompatMode) -> anyhow::Result<()> {
-    info!("Patching assets with {:?} with locale mode {:?}", patch, locale_mode);
+/// Options of the patch command
+pub struct PatchOptions<'a> {
+    pub locale_mode: &'a I18nCompatMode,
+    pub resource_name: &'a str,
+    /// Apply the patch without touching the game directory and report what would change
+    pub dry_run: bool,
+    /// Where to save a JSON report of the changes
+    pub report: Option<&'a Path>,
+    /// Write the patched game files to this directory instead, leaving the game directory untouched
+    pub output: Option<&'a Path>,
+    /// Keep running and re-apply the patches whenever one of the patch directories changes
+    pub watch: bool,
+}
+
+/// Applies the patch directories to the game files, in load order
+pub fn patch(args: &Args, patches: &[PathBuf], options: &PatchOptions) -> anyhow::Result<()> {
+    let PatchOptions { locale_mode, resource_name, dry_run, .. } = *options;
+    info!("Patching assets with {:?} with locale mode {:?}", patches, locale_mode);
+
+    for patch in patches {
+        if !patch.is_dir() {
+            anyhow::bail!("Patch directory {:?} does not exist", patch);
+        }
+    }
 
-    if !patch.is_dir() {
-        anyhow::bail!("Patch directory {:?} does not exist", patch);
+    check_resource_name(resource_name)?;
+    if options.watch {
+        if let Some(output) = options.output {
+            watch::check_outside(patches, output, "output directory")?;
+        }
+        if let Some(report) = options.report {
+            watch::check_outside(patches, report, "report file")?;
+        }
+    }
+    // if game_dir is not already PapersPlease_Data, append it
+    let game_dir = if args.game_dir.ends_with(DATA_FOLDER_NAME) {
+        args.game_dir.clone()
+    } else {
+        args.game_dir.join(DATA_FOLDER_NAME)
+    };
+    if !game_dir.is_dir() {
+        anyhow::bail!("Game directory {:?} does not exist", game_dir);
     }
 
-    let game_files = prepare_game_files(&args.game_dir)?;
+    // nothing in the game directory is written to, not even the backups
+    let read_only = dry_run || options.output.is_some();
+    let previous_manifest = InstallManifest::load(&game_dir)?.unwrap_or_default();
+    let game_files = prepare_game_files(&game_dir, &previous_manifest, read_only)?;
 
-    let temp_dir = create_temp_dir();
-    let temp_unpacked = temp_dir.join("unpacked");
-    fs::create_dir_all(&temp_unpacked)
-        .context("Failed to create temp directory")?;
-    let audio_patches = patch.join("audio_patches.json");
-    let process_audio =  audio_patches.is_file();
+    let workspace = Workspace::create(args.keep_temp)?;
+    let process_audio = !audio_patch_files(patches).is_empty();
 
-    let mut repack_info = unpack_assets(args, &game_files.assets, &temp_unpacked, process_audio)?;
-    if process_audio {
-        patch_audio(&audio_patches, &game_files.game_dir, &mut repack_info)?;
+    let art_key = args.art_key.as_deref().context("Missing art key")?;
+    let mut manifest = InstallManifest::new(patches, art_key)?;
+    let output_dir = if dry_run {
+        // nothing is written on a dry run, the files only end up in the temp directory
+        workspace.join("output")
+    } else {
+        options.output.map_or_else(|| game_files.game_dir.clone(), Path::to_path_buf)
+    };
+    if read_only {
+        if process_audio && options.output.is_none() {
+            // only checks that the resource file can be written, nothing is saved
+            manifest.record_created(&game_files.game_dir, resource_name, &previous_manifest)?;
+        }
+    } else {
+        record_changes(&mut manifest, &game_files.game_dir, &previous_manifest, locale_mode, process_audio, resource_name)?;
+        // Save everything that might get touched up front, so a failed patch can still be reverted
+        let mut pending = manifest.clone();
+        pending.merge(&previous_manifest);
+        pending.save(&game_files.game_dir)?;
     }
-    let patched_dir = patch_assets(patch, &temp_dir, &game_files.game_dir, repack_info)?;
 
-    if locale_mode == &I18nCompatMode::Normal {
-        patch_locale(&patched_dir, &game_files.game_dir)?;
+    let unpack_options = AssetsUnpackOptions { audio: process_audio, ..Default::default() };
+    let vanilla = unpack_assets(args, &game_files.assets, &workspace.join("unpacked"), unpack_options, Some(&game_files.assets_hash))?;
+
+    let mut run = PatchRun {
+        patches,
+        options,
+        game_files,
+        previous_manifest,
+        manifest,
+        workspace,
+        output_dir,
+        read_only,
+        process_audio,
+        keep_temp: args.keep_temp,
+    };
+    let mut state = PatchState::new(vanilla, dry_run);
+    if !options.watch {
+        return run.apply(&mut state, &Changes::all());
+    }
+
+    // the vanilla and patched assets stay in memory, so a change only re-applies what it affects
+    let mut pending = Changes::all();
+    match run.apply(&mut state, &pending) {
+        Ok(()) => pending = Changes::default(),
+        Err(e) => error!("Failed to apply the patch: {:#}", e),
+    }
+    watch::watch(patches, |changed| {
+        if !process_audio && changed.iter().any(|path| path.ends_with("audio_patches.json")) {
+            warn!("audio_patches.json was added, restart the watch to patch audio");
+        }
+        // changes that failed to apply are retried together with the new ones
+        pending.extend(Changes::from_paths(patches, changed));
+        // refresh the hashes and timestamp, the recorded files stay the same
+        let mut manifest = InstallManifest::new(patches, art_key)?;
+        manifest.files = std::mem::take(&mut run.manifest.files);
+        run.manifest = manifest;
+        run.apply(&mut state, &pending)?;
+        pending = Changes::default();
+        Ok(())
+    })
+}
+
+/// The patched assets, kept between applies in watch mode so a change only has to re-apply and
+/// serialize the entries and objects it affects
+struct PatchState {
+    vanilla: RepackInfo,
+    patched: RepackInfo,
+    /// Encrypted patched Art.dat
+    art_data: Vec<u8>,
+    /// Serialized data of the replaced objects, by path id
+    serialized: HashMap<i64, Vec<u8>>,
+    report: PatchReport,
+}
+
+impl PatchState {
+    fn new(vanilla: RepackInfo, dry_run: bool) -> Self {
+        Self {
+            patched: vanilla.clone(),
+            vanilla,
+            art_data: Vec::new(),
+            serialized: HashMap::new(),
+            report: PatchReport::new(dry_run),
+        }
     }
+}
+
+/// Everything needed to apply a patch to the vanilla assets, which stays the same when the patch
+/// is re-applied in watch mode
+struct PatchRun<'a> {
+    /// Patch directories in load order
+    patches: &'a [PathBuf],
+    options: &'a PatchOptions<'a>,
+    game_files: GameFiles,
+    previous_manifest: InstallManifest,
+    /// Manifest with the files this patch overwrites or creates
+    manifest: InstallManifest,
+    workspace: Workspace,
+    /// Where the patched game files end up
+    output_dir: PathBuf,
+    /// Whether the patched files are written somewhere else than the game directory
+    read_only: bool,
+    process_audio: bool,
+    keep_temp: bool,
+}
+
+impl PatchRun<'_> {
+    /// Applies the changed parts of the patch to the patched assets and writes the patched game files
+    fn apply(&self, state: &mut PatchState, changes: &Changes) -> anyhow::Result<()> {
+        let PatchOptions { locale_mode, resource_name, dry_run, report: report_path, output, .. } = *self.options;
+        let patches = self.patches;
+        let game_files = &self.game_files;
+        let PatchState { vanilla, patched, art_data, serialized, report } = state;
+        let mut staging = if self.read_only {
+            StagedWrites::redirected(&game_files.game_dir, &self.output_dir)
+        } else {
+            StagedWrites::default()
+        };
+        report.removed.clear();
+        report.outputs.clear();
+
+        // path ids of the replaced objects that have to be serialized again
+        let mut changed = Vec::new();
+        let patch_audio_files = self.process_audio && changes.audio;
+        if patch_audio_files {
+            // the audio patch lists are layered, so they're applied again as a whole
+            changed.extend(patched.audio_assets.keys().copied());
+            changed.extend(patched.remove_new_objects(unity::AUDIO_CLIP_CLASS));
+            patched.audio_assets = vanilla.audio_assets.clone();
+            report.clear_objects(ObjectClass::AudioClip);
+            patch_audio(&audio_patch_files(patches), &game_files.game_dir, resource_name, patched, &mut staging, report)?;
+            changed.extend(patched.audio_assets.keys().copied());
+        }
+        changed.extend(patch_text_assets(patches, &changes.text_assets, patched, report)?);
+        let textures = patch_textures(patches, &changes.textures, patched, report)?;
+        // sprite layouts depend on the size of their texture
+        if changes.sprites || !textures.is_empty() {
+            let previous = std::mem::take(&mut patched.sprites);
+            report.clear_objects(ObjectClass::Sprite);
+            let result = patches.iter()
+                .map(|patch| patch.join("sprites.json"))
+                .filter(|sprite_patches| sprite_patches.is_file())
+                .try_for_each(|sprite_patches| patch_sprites(&sprite_patches, patched, report));
+            if let Err(e) = result {
+                // the previous sprites still have to be serialized again when this is retried
+                patched.sprites = previous;
+                return Err(e);
+            }
+            changed.extend(previous.into_keys());
+            changed.extend(patched.sprites.keys().copied());
+        }
+        changed.extend(textures);
+        let art_changed = patch_assets(patches, &changes.art, &vanilla.art, &mut patched.art, report)?;
+
+        if !patch_audio_files && !art_changed && changed.is_empty() {
+            info!("Nothing to re-apply");
+            return Ok(());
+        }
+        serialize_objects(patched, changed, serialized)?;
+        if art_changed {
+            info!("Encrypting assets...");
+            *art_data = patched.art.encrypt(&patched.art_key)?;
+            if self.keep_temp {
+                patched.art.write_to(&self.workspace.join("patched"))?;
+            }
+        }
+        pack_to_assets(&game_files.game_dir, patched, art_data, serialized, &mut staging)?;
+
+        // en.zip only has to be rewritten if one of the Art.dat entries it contains changed
+        if art_changed && locale_mode == &I18nCompatMode::Normal {
+            report.locale.clear();
+            patch_locale(&patched.art, &game_files.locale, &game_files.game_dir, &mut staging, report)?;
+        }
+        staging.commit()?;
+
+        // Files overwritten by an earlier patch stay modified, files it created are no longer needed
+        let mut manifest = self.manifest.clone();
+        let previous_files = if output.is_none() { self.previous_manifest.files.as_slice() } else { &[] };
+        for file in previous_files {
+            if manifest.get(&file.path).is_some() {
+                continue;
+            }
+            if file.change == FileChange::Created {
+                let path = game_files.game_dir.join(&file.path);
+                if path.exists() {
+                    report.removed.push(file.path.clone());
+                    if !dry_run {
+                        info!("Removing {} created by a previous patch", file.path);
+                        fs::remove_file(&path)
+                            .with_context(|| format!("Failed to remove {}", file.path))?;
+                    }
+                }
+            } else {
+                manifest.files.push(file.clone());
+            }
+        }
 
-    info!("Cleaning up...");
-    fs::remove_dir_all(&temp_dir).context("Failed to remove temp directory")?;
+        if let Some(report_path) = report_path {
+            let mut outputs = vec![ASSETS_FILE_NAME];
+            if locale_mode == &I18nCompatMode::Normal {
+                outputs.push(LOCALE_FILE_NAME);
+            }
+            if self.process_audio {
+                outputs.push(resource_name);
+            }
+            for output in outputs {
+                report.outputs.push(ReportedFile::from_file(output, &self.output_dir.join(output))?);
+            }
+            report.save(report_path)?;
+        }
 
+        if dry_run {
+            report.log_dry_run();
+            return Ok(());
+        }
+        if let Some(output) = output {
+            info!("Patched game files written to {}", output.display());
+            return Ok(());
+        }
+        manifest.record_patched_hashes(&game_files.game_dir)?;
+        manifest.save(&game_files.game_dir)?;
+        info!("Patch applied");
+
+        Ok(())
+    }
+}
+
+/// The audio patch lists of the patches, in load order
+fn audio_patch_files(patches: &[PathBuf]) -> Vec<PathBuf> {
+    patches.iter()
+        .map(|patch| patch.join("audio_patches.json"))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Files directly inside the `dir` subdirectory of the patches, in load order. A file replaces the
+/// file with the same name of an earlier patch.
+fn layered_files(patches: &[PathBuf], dir: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for patch in patches {
+        let dir = patch.join(dir);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in WalkDir::new(&dir).min_depth(1).max_depth(1) {
+            let file = file.map_err(|e| anyhow::anyhow!("Failed to walk directory: {}", e))?;
+            if !file.file_type().is_file() {
+                continue;
+            }
+            match files.iter_mut().find(|existing| existing.file_name() == Some(file.file_name())) {
+                Some(existing) => *existing = file.into_path(),
+                None => files.push(file.into_path()),
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Files of the `dir` subdirectories of the patches affected by changes, by file name, with the
+/// file of the last patch that has it (see [layered_files]), or `None` if no patch has it anymore.
+/// If the whole directory is affected, so are all files applied before, given by `applied`.
+fn affected_files(
+    patches: &[PathBuf],
+    dir: &str,
+    changed: &Affected,
+    applied: impl IntoIterator<Item = String>,
+) -> anyhow::Result<Vec<(String, Option<PathBuf>)>> {
+    let names = match changed {
+        Affected::Nothing => return Ok(Vec::new()),
+        Affected::Files(names) => names.clone(),
+        Affected::All => {
+            let mut names = applied.into_iter().collect::<BTreeSet<_>>();
+            for file in layered_files(patches, dir)? {
+                let name = file.file_name().and_then(OsStr::to_str)
+                    .with_context(|| format!("Invalid file name: {}", file.display()))?;
+                names.insert(name.to_string());
+            }
+            names
+        }
+    };
+
+    Ok(names.into_iter()
+        .map(|name| {
+            let file = patches.iter().rev()
+                .map(|patch| patch.join(dir).join(&name))
+                .find(|file| file.is_file());
+            (name, file)
+        })
+        .collect())
+}
+
+/// Records the game files this patch is going to overwrite or create
+fn record_changes(
+    manifest: &mut InstallManifest,
+    game_dir: &Path,
+    previous: &InstallManifest,
+    locale_mode: &I18nCompatMode,
+    process_audio: bool,
+    resource_name: &str,
+) -> anyhow::Result<()> {
+    manifest.record_overwritten(game_dir, ASSETS_FILE_NAME, &format!("{}-bak", ASSETS_FILE_NAME))?;
+    if locale_mode == &I18nCompatMode::Normal {
+        manifest.record_overwritten(game_dir, LOCALE_FILE_NAME, &format!("{}-bak", LOCALE_FILE_NAME))?;
+    }
+    if process_audio {
+        manifest.record_created(game_dir, resource_name, previous)?;
+    }
     Ok(())
 }
 
 //<editor-fold desc="Filesystem preparations" defaultstate="collapsed">
 pub struct GameFiles {
     pub game_dir: PathBuf,
+    /// Vanilla assets file
     pub assets: PathBuf,
+    /// Hash of the vanilla assets file
+    pub assets_hash: String,
+    /// Vanilla en.zip
+    pub locale: PathBuf,
 }
 
-fn prepare_game_files(game_dir: &PathBuf) -> anyhow::Result<GameFiles> {
-    // if game_dir is not already PapersPlease_Data, append it
-    let game_dir = if game_dir.ends_with(DATA_FOLDER_NAME) {
-        game_dir.clone()
+/// Makes sure there are backups of the vanilla game files. If the game directory is only read
+/// from, no backups are created and the live files are used instead if they are still vanilla.
+fn prepare_game_files(game_dir: &Path, previous: &InstallManifest, read_only: bool) -> anyhow::Result<GameFiles> {
+    let build = GameBuild::detect(game_dir)?;
+    let mut records = BackupRecords::load(game_dir)?;
+
+    let assets = prepare_file(game_dir, ASSETS_FILE_NAME, &build, &mut records, previous, read_only)?;
+    let locale = prepare_file(game_dir, LOCALE_FILE_NAME, &build, &mut records, previous, read_only)?;
+    let assets_hash = if read_only {
+        hash_file(&assets)?
     } else {
-        game_dir.join(DATA_FOLDER_NAME)
+        records.save(game_dir)?;
+        records.get(ASSETS_FILE_NAME)
+            .map(|record| record.hash.clone())
+            .context("Missing backup record of the assets file")?
     };
 
-    if !game_dir.is_dir() {
-        anyhow::bail!("Game directory {:?} does not exist", game_dir);
-    }
-
-    let assets = prepare_file(&game_dir, "sharedassets0.assets")?;
-    let _ = prepare_file(&game_dir, "StreamingAssets/loc/en.zip")?;
-
-    Ok(GameFiles { game_dir, assets})
+    Ok(GameFiles { game_dir: game_dir.to_path_buf(), assets, assets_hash, locale })
 }
 
-fn prepare_file(game_dir: &PathBuf, name: &str) -> anyhow::Result<PathBuf> {
+/// Returns the backup of a game file, creating it if needed. If the live file changed since the
+/// backup was taken and wasn't patched by us, the game was updated and the backup is refreshed,
+/// unless the game build is still the same, in which case we can't tell which file is vanilla.
+fn prepare_file(
+    game_dir: &Path,
+    name: &str,
+    build: &GameBuild,
+    records: &mut BackupRecords,
+    previous: &InstallManifest,
+    read_only: bool,
+) -> anyhow::Result<PathBuf> {
+    let backup_name = format!("{}-bak", name);
+    let copy_file = game_dir.join(&backup_name);
+    let file = game_dir.join(name);
 
     // check if backup file of original file already exists
-    let copy_file = game_dir.join(format!("{}-bak", name));
     if copy_file.exists() {
-        return Ok(copy_file);
-    }
+        let Some(record) = records.get(name) else {
+            // backup from a version without backup records, trust it like before
+            records.record(game_dir, name, &backup_name, build)?;
+            return Ok(copy_file);
+        };
 
-    // check if original file exists and create a backup
-    let file = game_dir.join(name);
-    if !file.exists() {
+        if hash_file(&copy_file)? != record.hash {
+            anyhow::bail!("Backup {} was modified. Verify game integrity in steam and remove it to create a new one.", backup_name);
+        }
+        if !file.exists() {
+            return Ok(copy_file);
+        }
+
+        let live_hash = hash_file(&file)?;
+        let patched_hash = previous.get(name).and_then(|file| file.patched_hash.as_ref());
+        if live_hash == record.hash || patched_hash == Some(&live_hash) {
+            return Ok(copy_file);
+        }
+
+        if &record.build == build {
+            anyhow::bail!("{} changed since it was backed up, but the game build is the same ({}). \
+                Verify game integrity in steam or run revert, then patch again.", name, build);
+        }
+        info!("Game was updated since {} was backed up ({} -> {}), refreshing the backup", name, record.build, build);
+    } else if !file.exists() {
+        // check if original file exists and create a backup
         anyhow::bail!("Couldn't find {} in game directory {:?}", name, game_dir);
     }
 
+    if read_only {
+        // the live file is vanilla, use it without creating a backup
+        return Ok(file);
+    }
     fs::copy(&file, &copy_file)
         .map_err(|e| anyhow::anyhow!("Failed to create backup of {}: {}", name, e))?;
+    records.record(game_dir, name, &backup_name, build)?;
 
     Ok(copy_file)
 }
 
-fn create_temp_dir() -> PathBuf {
-    let mut temp_dir = temp_dir();
-    temp_dir.push("papers-tools");
-    temp_dir.push(format!("papers_please_assets_{}", random::<u64>()));
-    temp_dir
-}
 //</editor-fold>
\ No newline at end of file
@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+use tracing::info;
+
+use crate::command::manifest::hash_file;
+
+/// Everything a patch changed, or would change on a dry run. Can be saved as JSON for tools
+/// wrapping papers-tools.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchReport {
+    pub tool_version: String,
+    pub dry_run: bool,
+    /// Art.dat files replaced by a file of the patch
+    pub replaced: Vec<ReportedFile>,
+    /// Art.dat files an xml patch was merged into
+    pub merged: Vec<ReportedFile>,
+    /// Files added to Art.dat
+    pub added: Vec<ReportedFile>,
+    /// Art.dat files copied without changes
+    pub untouched: Vec<ReportedFile>,
+    /// Unity objects that were replaced or added
+    pub objects: Vec<ObjectChange>,
+    /// en.zip entries updated from the patched Art.dat
+    pub locale: Vec<ReportedFile>,
+    /// Files created by an earlier patch that are no longer needed
+    pub removed: Vec<String>,
+    /// Game files written by the patch
+    pub outputs: Vec<ReportedFile>,
+}
+
+/// A file with the size and MD5 hash of its (patched) content
+#[derive(Debug, Serialize)]
+pub struct ReportedFile {
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+impl ReportedFile {
+    pub fn new(path: &str, data: &[u8]) -> Self {
+        Self {
+            path: path.to_string(),
+            size: data.len() as u64,
+            hash: format!("{:x}", md5::compute(data)),
+        }
+    }
+
+    pub fn from_file(path: &str, file: &Path) -> anyhow::Result<Self> {
+        let size = fs::metadata(file)
+            .with_context(|| format!("Failed to read metadata of {}", file.display()))?
+            .len();
+        Ok(Self { path: path.to_string(), size, hash: hash_file(file)? })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectChange {
+    pub class: ObjectClass,
+    pub name: String,
+    /// Whether the object is new, instead of replacing an existing one
+    pub added: bool,
+    /// Size of the new object data, e.g. the FSB file of an audio clip. Sprites only change
+    /// their layout and have none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ObjectClass {
+    AudioClip,
+    TextAsset,
+    Texture,
+    Sprite,
+}
+
+impl Display for ObjectClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ObjectClass::AudioClip => "audio clip",
+            ObjectClass::TextAsset => "text asset",
+            ObjectClass::Texture => "texture",
+            ObjectClass::Sprite => "sprite",
+        })
+    }
+}
+
+impl PatchReport {
+    pub fn new(dry_run: bool) -> Self {
+        Self { tool_version: env!("CARGO_PKG_VERSION").to_string(), dry_run, ..Default::default() }
+    }
+
+    /// Records a replaced or added object with its new data
+    pub fn object(&mut self, class: ObjectClass, name: &str, added: bool, data: Option<&[u8]>) {
+        self.objects.push(ObjectChange {
+            class,
+            name: name.to_string(),
+            added,
+            size: data.map(|data| data.len() as u64),
+            hash: data.map(|data| format!("{:x}", md5::compute(data))),
+        });
+    }
+
+    /// Drops what was recorded for an Art.dat file, before it is patched again
+    pub fn clear_file(&mut self, path: &str) {
+        for files in [&mut self.replaced, &mut self.merged, &mut self.added, &mut self.untouched] {
+            files.retain(|file| file.path != path);
+        }
+    }
+
+    /// Drops what was recorded for an object, before it is patched again
+    pub fn clear_object(&mut self, class: ObjectClass, name: &str) {
+        self.objects.retain(|object| object.class != class || object.name != name);
+    }
+
+    /// Drops what was recorded for all objects of a class
+    pub fn clear_objects(&mut self, class: ObjectClass) {
+        self.objects.retain(|object| object.class != class);
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_string_pretty(self)
+            .context("Failed to serialize patch report")?;
+        fs::write(path, data)
+            .with_context(|| format!("Failed to write patch report to {}", path.display()))?;
+        info!("Saved patch report to {}", path.display());
+        Ok(())
+    }
+
+    /// Logs what a dry run would have done
+    pub fn log_dry_run(&self) {
+        info!("Dry run finished, the game directory was not modified. The patch would:");
+        log_list("Replace Art.dat files", &paths(&self.replaced));
+        log_list("Merge xml patches into Art.dat files", &paths(&self.merged));
+        log_list("Add Art.dat files", &paths(&self.added));
+        let replaced = self.objects.iter()
+            .filter(|object| !object.added)
+            .map(|object| format!("{} ({})", object.name, object.class))
+            .collect::<Vec<_>>();
+        log_list("Replace objects", &replaced);
+        let added = self.objects.iter()
+            .filter(|object| object.added)
+            .map(|object| format!("{} ({})", object.name, object.class))
+            .collect::<Vec<_>>();
+        log_list("Add objects", &added);
+        log_list("Update en.zip entries", &paths(&self.locale));
+        log_list("Remove files created by the previous patch", &self.removed);
+        log_list("Leave Art.dat files untouched", &paths(&self.untouched));
+    }
+}
+
+fn paths(files: &[ReportedFile]) -> Vec<String> {
+    files.iter().map(|file| file.path.clone()).collect()
+}
+
+fn log_list(title: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    info!("{} ({}):", title, items.len());
+    for item in items {
+        info!("  {}", item);
+    }
+}
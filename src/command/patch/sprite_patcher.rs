@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::command::patch::report::{ObjectClass, PatchReport};
+use crate::command::unpack::RepackInfo;
+use crate::unity::context::AssetsContext;
+use crate::unity::sprite::{Rectf, Sprite, Vector2f, Vector4f};
+use crate::unity::texture::Texture2D;
+
+type SpritePatchList = Vec<SpritePatch>;
+
+/// Layout changes of a sprite. Uses the same format as the `sprites.json` written when unpacking,
+/// so entries can be copied over from there. Fields that aren't set keep their original value.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpritePatch {
+    pub object_name: String,
+    pub rect: Option<Rectf>,
+    pub pivot: Option<Vector2f>,
+    pub border: Option<Vector4f>,
+}
+
+pub fn patch_sprites(sprite_patches_path: &Path, repack_info: &mut RepackInfo, report: &mut PatchReport) -> anyhow::Result<()> {
+    let sprite_patches = fs::read_to_string(sprite_patches_path)
+        .context("Failed to read sprite patches file")?;
+    let sprite_patches: SpritePatchList = serde_json::from_str(&sprite_patches)
+        .context("Failed to parse sprite patches file")?;
+
+    let mut patched = 0;
+    for patch in &sprite_patches {
+        let (path_id, _) = repack_info.sprite_names.get(&patch.object_name)
+            .with_context(|| format!("Sprite {} in sprite patches does not exist in the assets file", patch.object_name))?;
+        let path_id = *path_id;
+        // start from the sprite patched by an earlier patch, if any
+        let mut sprite: Sprite = match repack_info.sprites.get(&path_id) {
+            Some(sprite) => sprite.clone(),
+            None => repack_info.read_object(path_id, ())?,
+        };
+
+        let rect = patch.rect.unwrap_or(sprite.rect);
+        let pivot = patch.pivot.unwrap_or(sprite.pivot);
+        let border = patch.border.unwrap_or(sprite.border);
+        if rect == sprite.rect && pivot == sprite.pivot && border == sprite.border {
+            continue;
+        }
+
+        if rect != sprite.rect || pivot != sprite.pivot {
+            let texture_size = texture_size(&sprite, repack_info)
+                .with_context(|| format!("Failed to get texture of sprite {}", patch.object_name))?;
+            sprite.set_layout(rect, pivot, texture_size, repack_info.assets.endian())
+                .with_context(|| format!("Failed to change layout of sprite {}", patch.object_name))?;
+        }
+        sprite.border = border;
+
+        info!("Patching sprite: {}", patch.object_name);
+        report.object(ObjectClass::Sprite, &patch.object_name, false, None);
+        repack_info.sprites.insert(path_id, sprite);
+        patched += 1;
+    }
+
+    info!("Patched {} sprites", patched);
+    Ok(())
+}
+
+/// Returns the size of the texture a sprite is on, taking replaced textures into account
+fn texture_size(sprite: &Sprite, repack_info: &RepackInfo) -> anyhow::Result<(f32, f32)> {
+    let texture = sprite.render_data.texture;
+    let size = if texture.file_id != 0 {
+        // textures in other files can't be patched, so just read the original one
+        let mut context = AssetsContext::load(&repack_info.original_assets)?;
+        let obj = context.resolve(AssetsContext::ROOT, texture)?
+            .context("Sprite has no texture")?;
+        let version = context.file(obj.file).assets.unity_version()?;
+        let texture: Texture2D = context.read_object(obj, (version,))?;
+        (texture.width, texture.height)
+    } else if let Some(texture) = repack_info.textures.get(&texture.path_id) {
+        (texture.width, texture.height)
+    } else {
+        let texture: Texture2D = repack_info.read_object(texture.path_id, (repack_info.unity_version,))?;
+        (texture.width, texture.height)
+    };
+    Ok((size.0 as f32, size.1 as f32))
+}
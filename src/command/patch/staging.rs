@@ -0,0 +1,148 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::{error, warn};
+use crate::command::workspace::{register_cleanup, unregister_cleanup};
+
+const STAGED_SUFFIX: &str = ".papers-tools-new";
+const REPLACED_SUFFIX: &str = ".papers-tools-old";
+
+/// Game files written by a patch. Everything is written to temporary files next to the targets
+/// first and only moved into place once the whole patch succeeded, so a failing patch never
+/// leaves the game half patched.
+#[derive(Default)]
+pub struct StagedWrites {
+    files: Vec<StagedFile>,
+    redirect: Option<Redirect>,
+}
+
+/// Writes game files into another directory instead, at the same path relative to the game directory
+struct Redirect {
+    game_dir: PathBuf,
+    output: PathBuf,
+}
+
+struct StagedFile {
+    staged: PathBuf,
+    target: PathBuf,
+}
+
+impl StagedWrites {
+    /// Writes every file into `output` instead of the game directory. Nothing is staged, so
+    /// committing doesn't touch the game directory.
+    pub fn redirected(game_dir: &Path, output: &Path) -> Self {
+        let redirect = Redirect { game_dir: game_dir.to_path_buf(), output: output.to_path_buf() };
+        Self { files: Vec::new(), redirect: Some(redirect) }
+    }
+
+    /// Creates the temporary file that will replace `target` on commit
+    pub fn create(&mut self, target: &Path) -> anyhow::Result<File> {
+        if let Some(redirect) = &self.redirect {
+            let relative = target.strip_prefix(&redirect.game_dir)
+                .with_context(|| format!("{} is not in the game directory", target.display()))?;
+            let path = redirect.output.join(relative);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            return File::create(&path)
+                .with_context(|| format!("Failed to create {}", path.display()));
+        }
+
+        let staged = with_suffix(target, STAGED_SUFFIX);
+        let file = File::create(&staged)
+            .with_context(|| format!("Failed to create {}", staged.display()))?;
+        register_cleanup(&staged);
+        self.files.retain(|file| file.target != target);
+        self.files.push(StagedFile { staged, target: target.to_path_buf() });
+        Ok(file)
+    }
+
+    /// Moves all staged files into place. If one of them can't be moved, the files that were
+    /// already replaced are rolled back.
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        let files = std::mem::take(&mut self.files);
+        for file in &files {
+            unregister_cleanup(&file.staged);
+        }
+        // replaced targets, with the location of their previous content if they existed
+        let mut committed: Vec<(&StagedFile, Option<PathBuf>)> = Vec::new();
+        for file in &files {
+            match commit_file(file) {
+                Ok(replaced) => committed.push((file, replaced)),
+                Err(e) => {
+                    for (file, replaced) in committed.iter().rev() {
+                        if let Err(e) = rollback_file(file, replaced.as_deref()) {
+                            error!("Failed to roll back {}: {}", file.target.display(), e);
+                        }
+                    }
+                    remove_staged(&files);
+                    return Err(e);
+                }
+            }
+        }
+
+        for (_, replaced) in committed {
+            if let Some(replaced) = replaced {
+                if let Err(e) = fs::remove_file(&replaced) {
+                    warn!("Failed to remove {}: {}", replaced.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedWrites {
+    /// Discards everything that wasn't committed
+    fn drop(&mut self) {
+        remove_staged(&self.files);
+    }
+}
+
+/// Moves a staged file into place, returning where the previous content of the target was moved
+fn commit_file(file: &StagedFile) -> anyhow::Result<Option<PathBuf>> {
+    let replaced = if file.target.exists() {
+        let replaced = with_suffix(&file.target, REPLACED_SUFFIX);
+        fs::rename(&file.target, &replaced)
+            .with_context(|| format!("Failed to move {} out of the way", file.target.display()))?;
+        Some(replaced)
+    } else {
+        None
+    };
+
+    if let Err(e) = fs::rename(&file.staged, &file.target) {
+        if let Some(replaced) = &replaced {
+            let _ = fs::rename(replaced, &file.target);
+        }
+        return Err(e).with_context(|| format!("Failed to move {} into place", file.target.display()));
+    }
+    Ok(replaced)
+}
+
+fn rollback_file(file: &StagedFile, replaced: Option<&Path>) -> anyhow::Result<()> {
+    match replaced {
+        Some(replaced) => fs::rename(replaced, &file.target)?,
+        None => fs::remove_file(&file.target)?,
+    }
+    Ok(())
+}
+
+fn remove_staged(files: &[StagedFile]) {
+    for file in files {
+        unregister_cleanup(&file.staged);
+        if file.staged.exists() {
+            if let Err(e) = fs::remove_file(&file.staged) {
+                warn!("Failed to remove {}: {}", file.staged.display(), e);
+            }
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::command::ART_OBJECT_NAME;
+use crate::command::patch::affected_files;
+use crate::command::patch::report::{ObjectClass, PatchReport};
+use crate::command::patch::watch::Affected;
+use crate::command::unpack::RepackInfo;
+use crate::unity;
+use crate::unity::text::TextAsset;
+use crate::unity::util::AlignedString;
+
+/// Replaces TextAssets with the files in the `textassets` directories of the patches, matched by
+/// their file name. Files that don't match an existing TextAsset are added as new TextAssets. Only
+/// the changed files are applied again, TextAssets whose file was removed are restored. Returns
+/// the path ids of the objects that changed.
+pub fn patch_text_assets(
+    patches: &[PathBuf],
+    changed: &Affected,
+    repack_info: &mut RepackInfo,
+    report: &mut PatchReport,
+) -> anyhow::Result<Vec<i64>> {
+    let applied = repack_info.text_asset_names.iter()
+        .filter(|(_, (path_id, _))| repack_info.text_assets.contains_key(path_id))
+        .map(|(name, _)| name.clone())
+        .chain(repack_info.new_objects.iter()
+            .filter(|obj| obj.class_id == unity::TEXT_ASSET_CLASS)
+            .map(|obj| obj.name.clone()))
+        .collect::<Vec<_>>();
+    let files = affected_files(patches, "textassets", changed, applied)?;
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    info!("Patching text assets...");
+    let mut changed_ids = Vec::new();
+    for (name, path) in files {
+        report.clear_object(ObjectClass::TextAsset, &name);
+        let existing = repack_info.text_asset_names.get(&name).cloned();
+        let Some(path) = path else {
+            // the file was removed, go back to the original text asset
+            if let Some((path_id, _)) = existing {
+                if repack_info.text_assets.remove(&path_id).is_some() {
+                    info!("Restoring text asset: {}", name);
+                }
+                changed_ids.push(path_id);
+            } else if let Some(path_id) = repack_info.remove_new_object(unity::TEXT_ASSET_CLASS, &name) {
+                info!("Removing new text asset: {}", name);
+                changed_ids.push(path_id);
+            }
+            continue;
+        };
+        if name == ART_OBJECT_NAME {
+            anyhow::bail!("{} can't be replaced as a text asset. Put the files to replace in the assets directory instead.", ART_OBJECT_NAME);
+        }
+
+        let script = std::fs::read(&path)
+            .with_context(|| format!("Failed to read text asset file {}", path.display()))?;
+
+        if let Some((path_id, object_name)) = existing {
+            info!("Replacing text asset: {}", name);
+            report.object(ObjectClass::TextAsset, &name, false, Some(&script));
+            let text_asset = TextAsset { object_name: AlignedString(object_name), script };
+            repack_info.text_assets.insert(path_id, text_asset);
+            changed_ids.push(path_id);
+        } else {
+            let text_asset = TextAsset { object_name: AlignedString(name), script };
+            let data = unity::serialize_object(&text_asset, repack_info.assets.endian(), ())
+                .context("Failed to serialize new text asset")?;
+            let path_id = repack_info.add_object(unity::TEXT_ASSET_CLASS, &text_asset.object_name, data);
+            info!("Added new text asset {} with path id {}", text_asset.object_name, path_id);
+            report.object(ObjectClass::TextAsset, &text_asset.object_name, true, Some(&text_asset.script));
+            changed_ids.push(path_id);
+        }
+    }
+
+    Ok(changed_ids)
+}
@@ -0,0 +1,119 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use binrw::io::BufReader;
+use tracing::info;
+
+use crate::command::patch::affected_files;
+use crate::command::patch::report::{ObjectClass, PatchReport};
+use crate::command::patch::watch::Affected;
+use crate::command::unpack::RepackInfo;
+use crate::unity;
+use crate::unity::texture::Texture2D;
+
+/// Replaces Texture2D objects with the PNG files in the `textures` directories of the patches,
+/// matched by their file name. PNG files that don't match an existing texture are added as new
+/// textures. Only the changed files are applied again, textures whose file was removed are
+/// restored. Returns the path ids of the objects that changed.
+pub fn patch_textures(
+    patches: &[PathBuf],
+    changed: &Affected,
+    repack_info: &mut RepackInfo,
+    report: &mut PatchReport,
+) -> anyhow::Result<Vec<i64>> {
+    let applied = repack_info.texture_names.iter()
+        .filter(|(_, (path_id, _))| repack_info.textures.contains_key(path_id))
+        .map(|(name, _)| format!("{}.png", name))
+        .chain(repack_info.new_objects.iter()
+            .filter(|obj| obj.class_id == unity::TEXTURE_2D_CLASS)
+            .map(|obj| format!("{}.png", obj.name)))
+        .collect::<Vec<_>>();
+    let files = affected_files(patches, "textures", changed, applied)?;
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    info!("Patching textures...");
+    let mut changed_ids = Vec::new();
+    for (file_name, path) in files {
+        let file_name = Path::new(&file_name);
+        if file_name.extension() != Some(OsStr::new("png")) {
+            match path {
+                Some(path) => anyhow::bail!("Unsupported texture file: {} (Only .png files are supported)", path.display()),
+                None => continue,
+            }
+        }
+        let name = file_name.file_stem().and_then(OsStr::to_str)
+            .with_context(|| format!("Invalid texture file name: {}", file_name.display()))?;
+        report.clear_object(ObjectClass::Texture, name);
+        let existing = repack_info.texture_names.get(name).map(|(path_id, _)| *path_id);
+        let Some(path) = path else {
+            // the file was removed, go back to the original texture
+            if let Some(path_id) = existing {
+                if repack_info.textures.remove(&path_id).is_some() {
+                    info!("Restoring texture: {}", name);
+                }
+                changed_ids.push(path_id);
+            } else if let Some(path_id) = repack_info.remove_new_object(unity::TEXTURE_2D_CLASS, name) {
+                info!("Removing new texture: {}", name);
+                changed_ids.push(path_id);
+            }
+            continue;
+        };
+
+        let (width, height, pixels) = read_png(&path)
+            .with_context(|| format!("Failed to read texture {}", path.display()))?;
+
+        if let Some(path_id) = existing {
+            let mut texture: Texture2D = repack_info.read_object(path_id, (repack_info.unity_version,))?;
+            let format = texture.texture_format;
+            texture.replace_image(width, height, &pixels)
+                .with_context(|| format!("Failed to replace texture {}", name))?;
+            if texture.texture_format != format {
+                info!("Replacing texture: {} (converted from format {} to {})", name, format.0, texture.texture_format.0);
+            } else {
+                info!("Replacing texture: {}", name);
+            }
+            report.object(ObjectClass::Texture, name, false, Some(&texture.image_data.data));
+            repack_info.textures.insert(path_id, texture);
+            changed_ids.push(path_id);
+        } else {
+            let mut texture = Texture2D::new(name.to_string(), repack_info.unity_version);
+            texture.replace_image(width, height, &pixels)
+                .with_context(|| format!("Failed to create texture {}", name))?;
+            let data = unity::serialize_object(&texture, repack_info.assets.endian(), (repack_info.unity_version,))
+                .context("Failed to serialize new texture")?;
+            let path_id = repack_info.add_object(unity::TEXTURE_2D_CLASS, name, data);
+            info!("Added new texture {} with path id {}", texture.object_name, path_id);
+            report.object(ObjectClass::Texture, &texture.object_name, true, Some(&texture.image_data.data));
+            changed_ids.push(path_id);
+        }
+    }
+
+    Ok(changed_ids)
+}
+
+/// Reads a PNG file and converts it to 8-bit RGBA pixels
+fn read_png(path: &Path) -> anyhow::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)
+        .context("Failed to open file")?));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()
+        .context("Failed to read PNG header")?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)
+        .context("Failed to read PNG data")?;
+    let buf = &buf[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf.to_vec(),
+        png::ColorType::Rgb => buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => anyhow::bail!("Indexed PNG images are not supported"),
+    };
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
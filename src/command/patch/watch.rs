@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+use notify::{Event, RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+
+/// How long to wait for further changes before re-applying the patch, since editors often write
+/// a file in several steps
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Calls `apply` with the changed files whenever something in one of the patch directories
+/// changes. Only returns if the watcher fails, errors of `apply` are logged and watching continues.
+pub fn watch<F>(patches: &[PathBuf], mut apply: F) -> anyhow::Result<()>
+where
+    F: FnMut(&BTreeSet<PathBuf>) -> anyhow::Result<()>,
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .context("Failed to create file watcher")?;
+    for patch in patches {
+        watcher.watch(patch, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", patch.display()))?;
+        info!("Watching {} for changes", patch.display());
+    }
+    info!("Press Ctrl-C to stop");
+
+    loop {
+        let mut changed = BTreeSet::new();
+        let event = receiver.recv().context("File watcher stopped")?;
+        collect_changes(event, &mut changed);
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            collect_changes(event, &mut changed);
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        for path in &changed {
+            info!("Changed: {}", path.display());
+        }
+        if let Err(e) = apply(&changed) {
+            error!("Failed to apply the patch: {:#}", e);
+        }
+        info!("Waiting for changes...");
+    }
+}
+
+/// Parts of the patch affected by changed files, so only those have to be applied again
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Changed paths below the `assets` directories, relative to the patch directory (e.g.
+    /// `assets/images/foo.png`). Every Art.dat entry at or below one of them is affected.
+    pub art: Affected,
+    /// Changed files in the `textassets` directories
+    pub text_assets: Affected,
+    /// Changed files in the `textures` directories
+    pub textures: Affected,
+    /// A `sprites.json` changed
+    pub sprites: bool,
+    /// An audio patch list or an audio file changed
+    pub audio: bool,
+}
+
+/// Changed files of one kind, by their path relative to the patch directory, or file name for
+/// the flat `textassets` and `textures` directories
+#[derive(Debug, Default)]
+pub enum Affected {
+    #[default]
+    Nothing,
+    Files(BTreeSet<String>),
+    /// Everything, e.g. because the whole directory was added or removed
+    All,
+}
+
+impl Affected {
+    fn insert(&mut self, file: String) {
+        match self {
+            Affected::Nothing => *self = Affected::Files(BTreeSet::from([file])),
+            Affected::Files(files) => {
+                files.insert(file);
+            }
+            Affected::All => {}
+        }
+    }
+
+    fn extend(&mut self, other: Affected) {
+        match other {
+            Affected::Nothing => {}
+            Affected::Files(files) => files.into_iter().for_each(|file| self.insert(file)),
+            Affected::All => *self = Affected::All,
+        }
+    }
+}
+
+impl Changes {
+    /// Everything, for the first time the patch is applied
+    pub fn all() -> Self {
+        Self { art: Affected::All, text_assets: Affected::All, textures: Affected::All, sprites: true, audio: true }
+    }
+
+    /// Maps changed files to the parts of the patch they affect
+    pub fn from_paths(patches: &[PathBuf], changed: &BTreeSet<PathBuf>) -> Self {
+        let patches = patches.iter().map(|patch| resolve(patch)).collect::<Vec<_>>();
+        let mut changes = Self::default();
+        for path in changed {
+            let path = resolve(path);
+            let Some(relative) = patches.iter().find_map(|patch| path.strip_prefix(patch).ok()) else {
+                continue;
+            };
+            // names that aren't valid UTF-8 can't be Art.dat entries or object names either
+            let Some(parts) = relative.iter().map(OsStr::to_str).collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            match parts.as_slice() {
+                // the patch directory itself
+                [] => changes.extend(Self::all()),
+                ["assets", ..] => changes.art.insert(parts.join("/")),
+                ["textassets"] => changes.text_assets = Affected::All,
+                ["textassets", name] => changes.text_assets.insert(name.to_string()),
+                ["textures"] => changes.textures = Affected::All,
+                ["textures", name] => changes.textures.insert(name.to_string()),
+                ["sprites.json"] => changes.sprites = true,
+                // only files directly in textassets and textures are used
+                ["textassets" | "textures", ..] => {}
+                // audio files can be anywhere in the patch, directories have no extension
+                _ => changes.audio |= relative.ends_with("audio_patches.json")
+                    || relative.extension().is_none_or(|ext| ext == OsStr::new("fsb") || ext == OsStr::new("wav")),
+            }
+        }
+        changes
+    }
+
+    /// Adds the changes of another set, e.g. ones that failed to apply before
+    pub fn extend(&mut self, other: Changes) {
+        self.art.extend(other.art);
+        self.text_assets.extend(other.text_assets);
+        self.textures.extend(other.textures);
+        self.sprites |= other.sprites;
+        self.audio |= other.audio;
+    }
+}
+
+/// Makes sure a file written by the patch isn't inside one of the watched patch directories,
+/// since writing it would trigger the watch again on every apply
+pub fn check_outside(patches: &[PathBuf], path: &Path, what: &str) -> anyhow::Result<()> {
+    let resolved = resolve(path);
+    for patch in patches {
+        if resolved.starts_with(resolve(patch)) {
+            anyhow::bail!("The {} {} is inside the watched patch directory {}, which would re-apply the patch endlessly",
+                what, path.display(), patch.display());
+        }
+    }
+    Ok(())
+}
+
+/// Absolute form of a path that may not exist yet, with symlinks in its existing part resolved
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut resolved = fs::canonicalize(existing).unwrap_or_else(|_| existing.to_path_buf());
+    resolved.extend(missing.iter().rev());
+    resolved
+}
+
+fn collect_changes(event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() => {
+            changed.extend(event.paths);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("File watcher error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(paths: &[&str]) -> Changes {
+        let patch = std::env::temp_dir().join(format!("papers-tools-watch-{}", rand::random::<u64>()));
+        let changed = paths.iter().map(|path| patch.join(path)).collect();
+        Changes::from_paths(&[patch], &changed)
+    }
+
+    fn files(affected: &Affected) -> Vec<&str> {
+        match affected {
+            Affected::Files(files) => files.iter().map(String::as_str).collect(),
+            _ => panic!("expected files, got {:?}", affected),
+        }
+    }
+
+    #[test]
+    fn maps_paths_to_patch_parts() {
+        let changes = changes(&[
+            "assets/images/desk.png",
+            "assets/data",
+            "textassets/Credits.txt",
+            "textures/Stamp.png",
+            "sprites.json",
+        ]);
+        assert_eq!(files(&changes.art), ["assets/data", "assets/images/desk.png"]);
+        assert_eq!(files(&changes.text_assets), ["Credits.txt"]);
+        assert_eq!(files(&changes.textures), ["Stamp.png"]);
+        assert!(changes.sprites);
+        assert!(!changes.audio);
+    }
+
+    #[test]
+    fn whole_directories_affect_everything_in_them() {
+        let changes = changes(&["textassets", "textures/Stamp.png", "textures"]);
+        assert!(matches!(changes.text_assets, Affected::All));
+        assert!(matches!(changes.textures, Affected::All));
+        assert!(matches!(changes.art, Affected::Nothing));
+    }
+
+    #[test]
+    fn audio_changes() {
+        assert!(changes(&["audio_patches.json"]).audio);
+        assert!(changes(&["sounds/stamp.wav"]).audio);
+        assert!(changes(&["sounds/stamp.fsb"]).audio);
+        assert!(changes(&["sounds"]).audio);
+        assert!(!changes(&["README.md"]).audio);
+        // subdirectories of textassets and textures aren't used
+        let changes = changes(&["textures/old/Stamp.png"]);
+        assert!(!changes.audio);
+        assert!(matches!(changes.textures, Affected::Nothing));
+    }
+
+    #[test]
+    fn patch_directory_itself_affects_everything() {
+        let changes = changes(&[""]);
+        assert!(matches!(changes.art, Affected::All));
+        assert!(matches!(changes.text_assets, Affected::All));
+        assert!(changes.sprites && changes.audio);
+    }
+
+    #[test]
+    fn extend_keeps_pending_changes() {
+        let mut pending = changes(&["textassets/A.txt"]);
+        pending.extend(changes(&["textassets/B.txt", "sprites.json"]));
+        assert_eq!(files(&pending.text_assets), ["A.txt", "B.txt"]);
+        assert!(pending.sprites);
+        pending.extend(changes(&["textassets"]));
+        assert!(matches!(pending.text_assets, Affected::All));
+    }
+}
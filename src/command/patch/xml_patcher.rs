@@ -1,22 +1,23 @@
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use anyhow::Context;
 use roxmltree::{Document, Node};
 use xml::{EmitterConfig, EventWriter};
 use xml::writer::XmlEvent;
 
-pub fn patch(original: &Path, patch: &PathBuf, output: &PathBuf) -> anyhow::Result<()> {
+/// Merges a patch XML file into the original XML of the Art.dat entry `name`
+pub fn patch(name: &str, original: &[u8], patch: &Path) -> anyhow::Result<Vec<u8>> {
     let patch_content = fs::read_to_string(patch)?;
     let patch_doc = Document::parse(&patch_content)
         .context("Failed to parse patch XML")?;
     let mut patch_index = build_index(&patch_doc);
 
-    let mut original_content = fs::read_to_string(original)
+    let mut original_content = String::from_utf8(original.to_vec())
         .context("Failed to parse original XML")?;
-    let needs_fix = original.file_name() == Some(OsStr::new("Facts.xml"));
+    let needs_fix = Path::new(name).file_name() == Some(OsStr::new("Facts.xml"));
 
     if needs_fix {
         original_content = original_content.replace("&&", "&amp;&amp;");
@@ -28,18 +29,19 @@ pub fn patch(original: &Path, patch: &PathBuf, output: &PathBuf) -> anyhow::Resu
         .perform_indent(true)
         .create_writer(Vec::with_capacity(original_content.len()));
 
-    if let Some(first) = original_doc.root().first_element_child() {
-        merge_to(&mut writer, first, &mut patch_index)
-            .with_context(|| format!("Failed to patch {} with {}", original.display(), patch.display()))?;
-        if needs_fix {
-            let content = String::from_utf8(writer.into_inner())?.replace("&amp;&amp;", "&&");
-            fs::write(output, content).context("Failed to write to output file")?
-        } else {
-            fs::write(output, writer.into_inner()).context("Failed to write to output file")?
+    match original_doc.root().first_element_child() {
+        Some(first) => {
+            merge_to(&mut writer, first, &mut patch_index)
+                .with_context(|| format!("Failed to patch {} with {}", name, patch.display()))?;
+            if needs_fix {
+                let content = String::from_utf8(writer.into_inner())?.replace("&amp;&amp;", "&&");
+                Ok(content.into_bytes())
+            } else {
+                Ok(writer.into_inner())
+            }
         }
+        None => Ok(original.to_vec()),
     }
-
-    Ok(())
 }
 
 type NodeIndex<'doc, 'input> = HashMap<String, HashMap<String, Node<'doc, 'input>>>;
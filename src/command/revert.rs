@@ -1,6 +1,9 @@
-use std::path::PathBuf;
-use tracing::info;
-use crate::command::DATA_FOLDER_NAME;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use crate::command::{ASSETS_FILE_NAME, DATA_FOLDER_NAME, LOCALE_FILE_NAME};
+use crate::command::manifest::{FileChange, hash_file, InstallManifest};
+use crate::command::patch::audio_patcher::DEFAULT_RESOURCE_NAME;
 
 pub fn revert(game_dir: &PathBuf) -> anyhow::Result<()> {
     // if game_dir is not already PapersPlease_Data, append it
@@ -13,24 +16,61 @@ pub fn revert(game_dir: &PathBuf) -> anyhow::Result<()> {
     if !game_dir.is_dir() {
         anyhow::bail!("Game directory {:?} does not exist", game_dir);
     }
-    
-    let assets = game_dir.join("sharedassets0.assets");
-    let assets_bak = game_dir.join("sharedassets0.assets-bak");
-    copy_backup(&assets, &assets_bak)?;
 
-    let locale = game_dir.join("StreamingAssets/loc/en.zip");
-    let locale_bak = game_dir.join("StreamingAssets/loc/en.zip-bak");
-    copy_backup(&locale, &locale_bak)?;
+    match InstallManifest::load(&game_dir)? {
+        Some(manifest) => revert_manifest(&game_dir, &manifest)?,
+        None => revert_legacy(&game_dir)?,
+    }
 
     info!("Reverted game files to vanilla state");
 
     Ok(())
 }
 
-fn copy_backup(file: &PathBuf, backup: &PathBuf) -> anyhow::Result<()> {
+/// Restores or removes every file recorded in the install manifest
+fn revert_manifest(game_dir: &Path, manifest: &InstallManifest) -> anyhow::Result<()> {
+    for file in &manifest.files {
+        let path = game_dir.join(&file.path);
+        match &file.change {
+            FileChange::Overwritten { backup, original_hash } => {
+                copy_backup(&path, &game_dir.join(backup))?;
+                if &hash_file(&path)? != original_hash {
+                    anyhow::bail!("Restored {} doesn't match the original file. You'll have to verify game integrity in steam to revert changes.", file.path);
+                }
+            }
+            FileChange::Created => {
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .map_err(|e| anyhow::anyhow!("Failed to remove {}: {}", file.path, e))?;
+                }
+            }
+        }
+    }
+
+    fs::remove_file(InstallManifest::path(game_dir))
+        .map_err(|e| anyhow::anyhow!("Failed to remove install manifest: {}", e))?;
+    Ok(())
+}
+
+/// Reverts a game patched by a version without install manifests
+fn revert_legacy(game_dir: &Path) -> anyhow::Result<()> {
+    warn!("No install manifest found, restoring the default files");
+    for name in [ASSETS_FILE_NAME, LOCALE_FILE_NAME] {
+        copy_backup(&game_dir.join(name), &game_dir.join(format!("{}-bak", name)))?;
+    }
+
+    let resource = game_dir.join(DEFAULT_RESOURCE_NAME);
+    if resource.exists() {
+        fs::remove_file(&resource)
+            .map_err(|e| anyhow::anyhow!("Failed to remove {}: {}", DEFAULT_RESOURCE_NAME, e))?;
+    }
+    Ok(())
+}
+
+fn copy_backup(file: &Path, backup: &Path) -> anyhow::Result<()> {
     if !backup.exists() {
         anyhow::bail!("Couldn't find {} in game directory {:?}. You'll to verify game integrity in steam to revert changes.", backup.display(), backup.parent());
     }
-    std::fs::copy(backup, file)?;
+    fs::copy(backup, file)?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use tracing::{info, warn};
+use crate::command::{ASSETS_FILE_NAME, DATA_FOLDER_NAME, LOCALE_FILE_NAME};
+use crate::command::manifest::{FileChange, hash_dir, hash_file, InstallManifest, InstalledFile};
+
+/// State of a file recorded in the install manifest
+#[derive(Debug, PartialEq, Eq)]
+enum FileState {
+    /// Unchanged since patching
+    Patched,
+    /// Back to the original content, or removed if it was created by the patch
+    Original,
+    Missing,
+    /// Changed by something else after patching, e.g. a game update
+    Modified,
+}
+
+/// Reports whether the game is vanilla, patched, or was modified after patching
+pub fn status(game_dir: &Path) -> anyhow::Result<()> {
+    // if game_dir is not already PapersPlease_Data, append it
+    let game_dir = if game_dir.ends_with(DATA_FOLDER_NAME) {
+        game_dir.to_path_buf()
+    } else {
+        game_dir.join(DATA_FOLDER_NAME)
+    };
+
+    if !game_dir.is_dir() {
+        anyhow::bail!("Game directory {:?} does not exist", game_dir);
+    }
+
+    let manifest = match InstallManifest::load(&game_dir)? {
+        Some(manifest) => manifest,
+        None => return status_without_manifest(&game_dir),
+    };
+
+    for patch in &manifest.patches {
+        info!("Patched with {} (hash {})", patch.dir.display(), patch.hash);
+    }
+    info!("Applied by papers-tools v{} at {} using key {}", manifest.tool_version, manifest.timestamp, manifest.art_key);
+    for patch in &manifest.patches {
+        if patch.dir.is_dir() && hash_dir(&patch.dir)? != patch.hash {
+            info!("The patch directory {} changed since it was applied", patch.dir.display());
+        }
+    }
+
+    let mut states = Vec::new();
+    for file in &manifest.files {
+        let state = file_state(&game_dir, file)?;
+        info!("  {}: {:?}", file.path, state);
+        states.push(state);
+    }
+
+    if states.iter().all(|state| *state == FileState::Patched) {
+        info!("Status: patched");
+    } else if states.iter().all(|state| *state == FileState::Original) {
+        info!("Status: vanilla (the install manifest is left over from an earlier patch)");
+    } else {
+        warn!("Status: patched, but game files were modified since (e.g. by a game update). Revert or verify game integrity in steam, then patch again.");
+    }
+
+    Ok(())
+}
+
+fn file_state(game_dir: &Path, file: &InstalledFile) -> anyhow::Result<FileState> {
+    let path = game_dir.join(&file.path);
+    if !path.exists() {
+        return Ok(if file.change == FileChange::Created { FileState::Original } else { FileState::Missing });
+    }
+
+    let hash = hash_file(&path)?;
+    if file.patched_hash.as_ref() == Some(&hash) {
+        return Ok(FileState::Patched);
+    }
+    match &file.change {
+        FileChange::Overwritten { original_hash, .. } if *original_hash == hash => Ok(FileState::Original),
+        _ => Ok(FileState::Modified),
+    }
+}
+
+/// Without a manifest, the game is either vanilla or was patched by an older version of the tool
+fn status_without_manifest(game_dir: &Path) -> anyhow::Result<()> {
+    for name in [ASSETS_FILE_NAME, LOCALE_FILE_NAME] {
+        let backup = game_dir.join(format!("{}-bak", name));
+        if backup.is_file() && hash_file(&backup)? != hash_file(&game_dir.join(name))? {
+            warn!("Status: patched by an older version of papers-tools ({} differs from its backup)", name);
+            return Ok(());
+        }
+    }
+    info!("Status: vanilla");
+    Ok(())
+}
@@ -1,28 +1,47 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use binrw::BinRead;
 use binrw::io::BufReader;
+use serde::Serialize;
 use tracing::{info, warn};
-use crate::{crypto, Args, unity};
-use crate::command::{ArtHeader, DATA_FOLDER_NAME};
-use crate::unity::AssetsFile;
+use crate::{Args, unity};
+use crate::command::{ART_OBJECT_NAME, DATA_FOLDER_NAME};
+use crate::command::art::ArtArchive;
+use crate::command::cache::ArtCache;
+use crate::command::manifest::hash_file;
+use crate::command::patch::audio_patcher::AudioPatch;
+use crate::unity::{AssetsFile, NewObject};
 use crate::unity::audio::AudioClip;
-use crate::unity::util::{AlignedString, AlignmentArgs};
+use crate::unity::context::AssetsContext;
+use crate::unity::resource::ResourceResolver;
+use crate::unity::sprite::{PPtr, Rectf, Sprite, Vector2f, Vector4f};
+use crate::unity::text::TextAsset;
+use crate::unity::texture::Texture2D;
+use crate::unity::util::{AlignedString, AlignmentArgs, UnityVersion};
 
-pub fn unpack(args: &Args, input: &Option<PathBuf>, output: &PathBuf) -> anyhow::Result<()> {
+pub fn unpack(args: &Args, input: &Option<PathBuf>, output: &PathBuf, audio: bool) -> anyhow::Result<()> {
     let input = &find_input(args, input)?;
     let extension = input.extension();
     match extension {
         Some(ext) => {
             if ext == OsStr::new("dat") || ext == OsStr::new("txt") {
+                if audio {
+                    warn!("Audio can only be extracted from .assets files");
+                }
                 unpack_dat(args, input, output)?;
             } else if ext == OsStr::new("assets") {
-                unpack_assets(args, input, output, false)?;
+                let options = AssetsUnpackOptions { art_files: true, audio, text_assets: true, textures: true, sprites: true };
+                let source_hash = hash_file(input)?;
+                let repack_info = unpack_assets(args, input, output, options, Some(&source_hash))?;
+                if audio {
+                    unpack_audio(&repack_info, output)?;
+                }
             } else {
                 anyhow::bail!("Input file has an invalid extension. (Supported: .dat, .assets)");
             }
@@ -35,7 +54,7 @@ pub fn unpack(args: &Args, input: &Option<PathBuf>, output: &PathBuf) -> anyhow:
     Ok(())
 }
 
-fn find_input(args: &Args, input: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
+pub fn find_input(args: &Args, input: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
     match input {
         // Check if an input path was provided
         Some(path) => {
@@ -60,62 +79,162 @@ fn find_input(args: &Args, input: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
     }
 }
 
-pub fn unpack_dat(args: &Args, input: &PathBuf, output: &PathBuf) -> anyhow::Result<()> {
-    let mut data = std::fs::read(input)
+pub fn unpack_dat(args: &Args, input: &PathBuf, output: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read(input)
         .context("Failed to read input file")?;
     info!("Unpacking assets from: {}", input.display());
 
     // key can be unwrapped safely here
-    let key = args.art_key.clone().unwrap();
-    let enc_key = crypto::to_key_array(key.as_str());
-    let enc_key_slice = enc_key.as_slice();
-    crypto::decrypt(enc_key_slice, data.as_mut_slice());
-
-    // Read header string
-    let len = u16::from_le_bytes([data[0], data[1]]) as usize;
-    let header = String::from_utf8(data[2..len + 2].to_vec())
-        .context("Failed to read header string")?;
-
-    let assets = haxeformat::from_str::<ArtHeader>(header.as_str())
-        .context("Failed to parse header string")?;
-
-    // Create output directory
-    std::fs::create_dir_all(&output)?;
-    let abs_output = Path::new(output).canonicalize()?;
-
-    // Loop through assets in the data and write them to the output directory
-    let mut index = len + 2;
-    for asset in &assets {
-        let asset_bytes = &data[index..index + asset.size];
-        index += asset.size;
-
-        let path = abs_output.join(&asset.name);
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
-            if !parent.canonicalize()?.starts_with(&abs_output) {
-                warn!("Skipping asset: {} (Tried escaping output directory)", asset.name);
-                continue;
-            }
-        }
+    let art_key = args.art_key.as_deref().unwrap();
+    let archive = load_or_decrypt(data, art_key)?;
+    archive.write_to(output)?;
+    info!("Unpacked {} assets", archive.entries.len());
 
-        std::fs::write(path, asset_bytes)
-            .context(format!("Failed to write asset {} to file", asset.name))?;
+    Ok(())
+}
+
+/// Decrypts an Art.dat file, using the cache keyed by the hash of its encrypted data
+fn load_or_decrypt(data: Vec<u8>, art_key: &str) -> anyhow::Result<ArtArchive> {
+    let source_hash = format!("{:x}", md5::compute(&data));
+    let cache = match ArtCache::open() {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            warn!("Not using the Art.dat cache: {}", e);
+            None
+        }
+    };
+    if let Some(archive) = cache.as_ref().and_then(|cache| cache.load(&source_hash, art_key)) {
+        return Ok(archive);
+    }
+    let archive = ArtArchive::decrypt(data, art_key)?;
+    if let Some(cache) = &cache {
+        if let Err(e) = cache.store(&source_hash, art_key, &archive) {
+            warn!("Failed to cache Art.dat: {}", e);
+        }
     }
+    Ok(archive)
+}
 
-    info!("Unpacked {} assets", assets.len());
+/// Selects what is read from an assets file besides the Art.dat object.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AssetsUnpackOptions {
+    /// Write the files of Art.dat to the `assets` output directory
+    pub art_files: bool,
+    /// Read all AudioClip objects into the repack info
+    pub audio: bool,
+    /// Write all TextAssets other than Art.dat to the `textassets` output directory
+    pub text_assets: bool,
+    /// Write all Texture2D objects as PNG files to the `textures` output directory
+    pub textures: bool,
+    /// Write the layout of all Sprite objects to `sprites.json` in the output directory
+    pub sprites: bool,
+}
 
-    Ok(())
+/// Layout of a sprite as written to `sprites.json` when unpacking
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpriteInfo {
+    object_name: String,
+    path_id: i64,
+    rect: Rectf,
+    pivot: Vector2f,
+    border: Vector4f,
+    pixels_to_units: f32,
+    packed: bool,
+    texture: PPtr,
+    /// File the texture is in, if it's not in the unpacked assets file
+    texture_file: Option<String>,
+    texture_name: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct RepackInfo {
     pub assets: AssetsFile,
     pub audio_assets: HashMap<i64, AudioClip>,
+    /// Path ids and object names of all TextAssets other than Art.dat, by their export file name
+    pub text_asset_names: HashMap<String, (i64, String)>,
+    pub text_assets: HashMap<i64, TextAsset>,
+    /// Path ids and object names of all Texture2D objects, by their export file name (without extension)
+    pub texture_names: HashMap<String, (i64, String)>,
+    pub textures: HashMap<i64, Texture2D>,
+    /// Path ids and object names of all Sprite objects, by their export name
+    pub sprite_names: HashMap<String, (i64, String)>,
+    pub sprites: HashMap<i64, Sprite>,
+    pub unity_version: UnityVersion,
     pub art_key: String,
+    /// Decrypted contents of the Art.dat object
+    pub art: ArtArchive,
     pub art_path_id: i64,
     pub original_assets: PathBuf,
+    pub new_objects: Vec<NewObject>,
+}
+
+impl RepackInfo {
+    /// Queues a new object to be appended to the assets file when repacking and returns the path id
+    /// assigned to it. If an object of the same class and name was already added, its data is
+    /// replaced and it keeps its path id.
+    pub fn add_object(&mut self, class_id: i32, name: &str, data: Vec<u8>) -> i64 {
+        if let Some(existing) = self.new_objects.iter_mut().find(|obj| obj.class_id == class_id && obj.name == name) {
+            existing.data = data;
+            return existing.path_id;
+        }
+        let path_id = match self.new_objects.iter().map(|obj| obj.path_id).max() {
+            Some(last) => last + 1,
+            None => self.assets.next_path_id(),
+        };
+        self.new_objects.push(NewObject { path_id, class_id, name: name.to_string(), data });
+        path_id
+    }
+
+    /// Removes a queued new object, returning its path id if there was one
+    pub fn remove_new_object(&mut self, class_id: i32, name: &str) -> Option<i64> {
+        let index = self.new_objects.iter().position(|obj| obj.class_id == class_id && obj.name == name)?;
+        Some(self.new_objects.remove(index).path_id)
+    }
+
+    /// Removes all queued new objects of a class, returning their path ids
+    pub fn remove_new_objects(&mut self, class_id: i32) -> Vec<i64> {
+        let (removed, kept) = std::mem::take(&mut self.new_objects).into_iter()
+            .partition::<Vec<_>, _>(|obj| obj.class_id == class_id);
+        self.new_objects = kept;
+        removed.into_iter().map(|obj| obj.path_id).collect()
+    }
+
+    /// Reads an object from the original assets file
+    pub fn read_object<T: BinRead>(&self, path_id: i64, args: T::Args<'_>) -> anyhow::Result<T> {
+        let obj = self.assets.content.objects.iter()
+            .find(|obj| obj.path_id == path_id)
+            .with_context(|| format!("Failed to find object with path id {}", path_id))?;
+        let mut input = BufReader::new(File::open(&self.original_assets)
+            .context("Failed to open original assets file")?);
+        input.seek(SeekFrom::Start(self.assets.header.offset_first_file + obj.byte_start))
+            .context("Failed to seek to object")?;
+        let mut data = vec![0; obj.byte_size as usize];
+        input.read_exact(&mut data)
+            .context("Failed to read object data")?;
+        let object = T::read_options(&mut Cursor::new(data), self.assets.endian(), args)
+            .with_context(|| format!("Failed to read object with path id {}", path_id))?;
+        Ok(object)
+    }
 }
 
-pub fn unpack_assets(args: &Args, input_path: &PathBuf, output: &PathBuf, process_audio: bool) -> anyhow::Result<RepackInfo> {
+pub fn unpack_assets(
+    args: &Args,
+    input_path: &PathBuf,
+    output: &PathBuf,
+    options: AssetsUnpackOptions,
+    source_hash: Option<&str>,
+) -> anyhow::Result<RepackInfo> {
+    // vanilla archives are cached by the hash of the assets file they're read from
+    let art_key = args.art_key.as_deref().context("Missing art key")?;
+    let cache = match source_hash.map(|_| ArtCache::open()).transpose() {
+        Ok(cache) => cache,
+        Err(e) => {
+            warn!("Not using the Art.dat cache: {}", e);
+            None
+        }
+    };
+
     let input = File::open(input_path)
         .context("Failed to open input file")?;
     let mut input = BufReader::new(input);
@@ -123,10 +242,22 @@ pub fn unpack_assets(args: &Args, input_path: &PathBuf, output: &PathBuf, proces
         .context("Failed to read assets file")?;
     let objects = assets.resolve_object_classes()
         .context("Failed to resolve object classes")?;
+    let version = assets.unity_version()?;
+    let resources = ResourceResolver::new(input_path)?;
 
-    let mut art_file: Option<PathBuf> = None;
+    let mut art: Option<ArtArchive> = None;
     let mut art_path_id: Option<i64> = None;
     let mut audio_assets = HashMap::new();
+    let mut text_asset_names = HashMap::new();
+    let mut used_names = HashSet::new();
+    let text_assets_dir = output.join("textassets");
+    let mut texture_names = HashMap::new();
+    let mut used_texture_names = HashSet::new();
+    let mut exported_textures = 0;
+    let textures_dir = output.join("textures");
+    let mut sprite_names = HashMap::new();
+    let mut used_sprite_names = HashSet::new();
+    let mut sprites = Vec::new();
     for obj in objects {
         if obj.class_id == unity::TEXT_ASSET_CLASS { // text asset
             input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
@@ -134,28 +265,88 @@ pub fn unpack_assets(args: &Args, input_path: &PathBuf, output: &PathBuf, proces
             let name = AlignedString::read_options(&mut input, assets.endian(), AlignmentArgs::new(4))
                 .context("Failed to read object name")?.0;
 
-            if name == "Art.dat" {
-                let temp = PathBuf::from("./temp-art.dat");
-                info!("Found Art.dat in unity assets. Temporarily saving to: {}", temp.display());
-
-                let temp_writer = File::create(&temp)
-                    .context("Failed to create temporary file")?;
-                let mut temp_writer = BufWriter::new(temp_writer);
+            if name == ART_OBJECT_NAME && art.is_none() {
+                info!("Found Art.dat in unity assets");
+                art_path_id = Some(obj.path_id);
+                if let (Some(cache), Some(hash)) = (&cache, source_hash) {
+                    if let Some(cached) = cache.load(hash, art_key) {
+                        art = Some(cached);
+                        continue;
+                    }
+                }
 
-                let to_copy = u32::read_options(&mut input, assets.endian(), ())
+                let len = u32::read_options(&mut input, assets.endian(), ())
                     .context("Failed to read asset length")?;
-                let mut temp_reader = input.by_ref().take(to_copy as u64);
+                let mut data = vec![0; len as usize];
+                input.read_exact(&mut data)
+                    .context("Failed to read Art.dat")?;
+
+                let archive = ArtArchive::decrypt(data, art_key)?;
+                if let (Some(cache), Some(hash)) = (&cache, source_hash) {
+                    if let Err(e) = cache.store(hash, art_key, &archive) {
+                        warn!("Failed to cache Art.dat: {}", e);
+                    }
+                }
+                art = Some(archive);
+                continue;
+            }
 
-                std::io::copy(&mut temp_reader, &mut temp_writer)
-                    .context("Failed to copy object data")?;
+            let file_name = export_file_name(&name, obj.path_id, &mut used_names);
+            if options.text_assets {
+                input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
+                    .context("Failed to seek to object")?;
+                let text_asset = TextAsset::read_options(&mut input, assets.endian(), ())
+                    .context("Failed to read TextAsset object")?;
+                std::fs::create_dir_all(&text_assets_dir)
+                    .context("Failed to create text assets directory")?;
+                std::fs::write(text_assets_dir.join(&file_name), &text_asset.script)
+                    .with_context(|| format!("Failed to write text asset {} to file", name))?;
+            }
+            text_asset_names.insert(file_name, (obj.path_id, name));
+        } else if obj.class_id == unity::TEXTURE_2D_CLASS {
+            input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
+                .context("Failed to seek to object")?;
+            let name = AlignedString::read_options(&mut input, assets.endian(), AlignmentArgs::new(4))
+                .context("Failed to read object name")?.0;
 
-                art_file = Some(temp);
-                art_path_id = Some(obj.path_id);
-                if !process_audio {
-                    break;
+            let file_name = export_file_name(&name, obj.path_id, &mut used_texture_names);
+            if options.textures {
+                input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
+                    .context("Failed to seek to object")?;
+                match Texture2D::read_options(&mut input, assets.endian(), (version,)) {
+                    Ok(texture) => {
+                        std::fs::create_dir_all(&textures_dir)
+                            .context("Failed to create textures directory")?;
+                        let path = textures_dir.join(format!("{}.png", file_name));
+                        match export_texture(&texture, &resources, &path) {
+                            Ok(()) => exported_textures += 1,
+                            Err(e) => warn!("Skipping texture {}: {}", name, e),
+                        }
+                    }
+                    Err(e) => warn!("Skipping texture {}: failed to read Texture2D object: {}", name, e),
                 }
             }
-        } else if process_audio && obj.class_id == unity::AUDIO_CLIP_CLASS {
+            texture_names.insert(file_name, (obj.path_id, name));
+        } else if obj.class_id == unity::SPRITE_CLASS {
+            input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
+                .context("Failed to seek to object")?;
+            let name = AlignedString::read_options(&mut input, assets.endian(), AlignmentArgs::new(4))
+                .context("Failed to read object name")?.0;
+
+            let export_name = export_file_name(&name, obj.path_id, &mut used_sprite_names);
+            if options.sprites {
+                input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
+                    .context("Failed to seek to object")?;
+                let mut data = vec![0; obj.byte_size as usize];
+                input.read_exact(&mut data)
+                    .context("Failed to read object data")?;
+                match Sprite::read_options(&mut Cursor::new(data), assets.endian(), ()) {
+                    Ok(sprite) => sprites.push((export_name.clone(), obj.path_id, sprite)),
+                    Err(e) => warn!("Skipping sprite {}: {}", name, e),
+                }
+            }
+            sprite_names.insert(export_name, (obj.path_id, name));
+        } else if options.audio && obj.class_id == unity::AUDIO_CLIP_CLASS {
             input.seek(SeekFrom::Start(assets.header.offset_first_file + obj.byte_start))
                 .context("Failed to seek to object")?;
 
@@ -165,21 +356,187 @@ pub fn unpack_assets(args: &Args, input_path: &PathBuf, output: &PathBuf, proces
         }
     }
 
-    if let Some(art_file) = art_file {
-        unpack_dat(args, &art_file, output)?;
-        info!("Removing temporary file: {}", art_file.display());
-        if let Err(e) = std::fs::remove_file(art_file) {
-            warn!("Failed to remove temporary file: {}", e);
+    if options.text_assets {
+        info!("Unpacked {} text assets", text_asset_names.len());
+    }
+    if options.textures {
+        info!("Unpacked {} of {} textures", exported_textures, texture_names.len());
+    }
+
+    if options.sprites {
+        let texture_names_by_id = texture_names.iter()
+            .map(|(file_name, (path_id, _))| (*path_id, file_name.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut context = AssetsContext::load(input_path)?;
+        let mut infos = Vec::new();
+        for (object_name, path_id, sprite) in sprites {
+            let texture = sprite.render_data.texture;
+            let (texture_file, texture_name) = if texture.file_id == 0 {
+                (None, texture_names_by_id.get(&texture.path_id).cloned())
+            } else {
+                // the texture is in another file, so look up its name there
+                match resolve_name(&mut context, texture) {
+                    Ok(Some((file, name))) => (Some(file), Some(name)),
+                    Ok(None) => (None, None),
+                    Err(e) => {
+                        warn!("Failed to resolve texture of sprite {}: {}", object_name, e);
+                        (None, None)
+                    }
+                }
+            };
+            infos.push(SpriteInfo {
+                object_name,
+                path_id,
+                rect: sprite.rect,
+                pivot: sprite.pivot,
+                border: sprite.border,
+                pixels_to_units: sprite.pixels_to_units,
+                packed: sprite.is_packed(),
+                texture,
+                texture_file,
+                texture_name,
+            });
+        }
+        std::fs::create_dir_all(output)
+            .context("Failed to create output directory")?;
+        let writer = BufWriter::new(File::create(output.join("sprites.json"))
+            .context("Failed to create sprites.json")?);
+        serde_json::to_writer_pretty(writer, &infos)
+            .context("Failed to write sprites.json")?;
+        info!("Unpacked layout of {} sprites", infos.len());
+    }
+
+    if let Some(art) = art {
+        if options.art_files {
+            art.write_to(output)?;
+            info!("Unpacked {} assets", art.entries.len());
         }
         // Any unwraps here are safe because None values would've resulted in earlier bail
         Ok(RepackInfo {
             assets,
             audio_assets,
+            text_asset_names,
+            text_assets: HashMap::new(),
+            texture_names,
+            textures: HashMap::new(),
+            sprite_names,
+            sprites: HashMap::new(),
+            unity_version: version,
+            art,
             art_path_id: art_path_id.unwrap(),
-            art_key: args.art_key.clone().unwrap(),
+            art_key: art_key.to_string(),
             original_assets: input_path.clone(),
+            new_objects: Vec::new(),
         })
     } else {
         anyhow::bail!("Failed to find Art.dat object in assets file");
     }
-}
\ No newline at end of file
+}
+
+/// Returns a file name to export an object with the given name to. Characters that aren't allowed
+/// in file names are replaced and objects sharing a name with an already exported object get
+/// their path id appended, so the same file names are generated for every run.
+pub fn export_file_name(name: &str, path_id: i64, used_names: &mut HashSet<String>) -> String {
+    let mut file_name: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    if file_name.is_empty() || file_name == "." || file_name == ".." {
+        file_name = format!("_{}", file_name);
+    }
+    if !used_names.insert(file_name.clone()) {
+        file_name = format!("{}-{}", file_name, path_id);
+        used_names.insert(file_name.clone());
+    }
+    file_name
+}
+
+/// Copies the FSB data of all audio clips to the `audio` output directory and writes an
+/// `audio_patches.json` template with the original settings of every clip next to it.
+fn unpack_audio(repack_info: &RepackInfo, output: &Path) -> anyhow::Result<()> {
+    let audio_dir = output.join("audio");
+    std::fs::create_dir_all(&audio_dir)
+        .context("Failed to create audio directory")?;
+    let resources = ResourceResolver::new(&repack_info.original_assets)?;
+
+    let mut clips = repack_info.audio_assets.iter().collect::<Vec<_>>();
+    clips.sort_by_key(|(path_id, _)| **path_id);
+
+    let mut used_names = HashSet::new();
+    let mut patches = Vec::new();
+    for (path_id, clip) in clips {
+        let file_name = format!("{}.fsb", export_file_name(&clip.object_name, *path_id, &mut used_names));
+        let mut reader = match resources.open(&clip.resource) {
+            Ok(reader) => reader,
+            Err(e) => {
+                warn!("Skipping audio clip {}: {}", clip.object_name, e);
+                continue;
+            }
+        };
+        let mut writer = BufWriter::new(File::create(audio_dir.join(&file_name))
+            .context("Failed to create audio file")?);
+        std::io::copy(&mut reader, &mut writer)
+            .with_context(|| format!("Failed to extract audio clip {}", clip.object_name))?;
+
+        patches.push(AudioPatch {
+            object_name: clip.object_name.to_string(),
+            patched_path: PathBuf::from(format!("audio/{}", file_name)),
+            load_type: Some(clip.load_type),
+            channels: Some(clip.channels),
+            frequency: Some(clip.frequency),
+            bits_per_sample: Some(clip.bits_per_sample),
+            length: Some(clip.length),
+            is_tracker_format: Some(*clip.is_tracker_format),
+            subsound_index: Some(clip.subsound_index),
+            preload_audio_data: Some(*clip.preload_audio_data),
+            load_in_background: Some(*clip.load_in_background),
+            legacy3d: Some(*clip.legacy3d),
+            compression_format: Some(clip.compression_format.clone()),
+            create: false,
+        });
+    }
+
+    let writer = BufWriter::new(File::create(output.join("audio_patches.json"))
+        .context("Failed to create audio_patches.json")?);
+    serde_json::to_writer_pretty(writer, &patches)
+        .context("Failed to write audio_patches.json")?;
+    info!("Unpacked {} audio clips", patches.len());
+
+    Ok(())
+}
+
+/// Decodes a texture and writes it to a PNG file
+fn export_texture(texture: &Texture2D, resources: &ResourceResolver, output: &Path) -> anyhow::Result<()> {
+    let data = read_texture_data(texture, resources)?;
+    let pixels = texture.decode(&data)?;
+
+    let writer = BufWriter::new(File::create(output)
+        .context("Failed to create texture file")?);
+    let mut encoder = png::Encoder::new(writer, texture.width as u32, texture.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()
+        .context("Failed to write PNG header")?;
+    writer.write_image_data(&pixels)
+        .context("Failed to write PNG data")?;
+    Ok(())
+}
+
+/// Returns the image data of a texture, reading it from its resource file if it isn't stored inline
+fn read_texture_data<'a>(texture: &'a Texture2D, resources: &ResourceResolver) -> anyhow::Result<Cow<'a, [u8]>> {
+    if !texture.image_data.data.is_empty() || texture.stream_data.size == 0 {
+        return Ok(Cow::Borrowed(&texture.image_data.data));
+    }
+    let data = resources.read(&texture.stream_data)
+        .context("Failed to read streamed texture data")?;
+    Ok(Cow::Owned(data))
+}
+
+/// Resolves a reference from the root file of the context and returns the file and name of the
+/// referenced object
+fn resolve_name(context: &mut AssetsContext, pptr: PPtr) -> anyhow::Result<Option<(String, String)>> {
+    let Some(obj) = context.resolve(AssetsContext::ROOT, pptr)? else {
+        return Ok(None);
+    };
+    let name = context.read_object_name(obj)?;
+    Ok(Some((context.file(obj.file).name.clone(), name)))
+}
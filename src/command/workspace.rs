@@ -0,0 +1,85 @@
+use std::env::temp_dir;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use rand::random;
+use tracing::{info, warn};
+
+/// Temporary files and directories that have to be removed if the tool is interrupted
+static CLEANUP_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Temporary directory for a command, under `papers-tools` in the system temp directory.
+/// It's removed when dropped, so also when the command fails, unless it should be kept for debugging.
+pub struct Workspace {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Workspace {
+    pub fn create(keep: bool) -> anyhow::Result<Self> {
+        let mut path = temp_dir();
+        path.push("papers-tools");
+        path.push(format!("papers_please_assets_{}", random::<u64>()));
+        fs::create_dir_all(&path)
+            .context("Failed to create temp directory")?;
+        if !keep {
+            register_cleanup(&path);
+        }
+        Ok(Self { path, keep })
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if self.keep {
+            info!("Keeping temp directory {}", self.path.display());
+            return;
+        }
+        info!("Cleaning up...");
+        remove_path(&self.path);
+        unregister_cleanup(&self.path);
+    }
+}
+
+/// Removes the path when the tool is interrupted with Ctrl-C
+pub fn register_cleanup(path: &Path) {
+    if let Ok(mut paths) = CLEANUP_PATHS.lock() {
+        paths.push(path.to_path_buf());
+    }
+}
+
+pub fn unregister_cleanup(path: &Path) {
+    if let Ok(mut paths) = CLEANUP_PATHS.lock() {
+        paths.retain(|p| p != path);
+    }
+}
+
+/// Removes all registered temporary files before exiting on Ctrl-C
+pub fn install_interrupt_handler() -> anyhow::Result<()> {
+    ctrlc::set_handler(|| {
+        warn!("Interrupted, cleaning up...");
+        if let Ok(paths) = CLEANUP_PATHS.lock() {
+            paths.iter().for_each(|path| remove_path(path));
+        }
+        std::process::exit(130);
+    }).context("Failed to install Ctrl-C handler")
+}
+
+fn remove_path(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else if path.exists() {
+        fs::remove_file(path)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = result {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::Context;
+
+use crate::fsb::{Fsb5, FsbCodec};
+
+/// Step sizes of IMA ADPCM
+const IMA_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408,
+    449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630,
+    9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+];
+const IMA_INDEX_ADJUST: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+/// Size of an IMA ADPCM block of one channel: 4 byte header followed by 64 nibbles
+const IMA_BLOCK_SIZE: usize = 0x24;
+
+/// Predictor coefficients of FMOD's own ADPCM codec
+const FADPCM_COEFS: [(i32, i32); 8] = [(0, 0), (60, 0), (122, 60), (115, 52), (98, 55), (0, 0), (0, 0), (0, 0)];
+/// Size of an FADPCM frame of one channel: 12 byte header followed by 256 nibbles
+const FADPCM_FRAME_SIZE: usize = 0x8C;
+
+/// Decodes a sample of a sound bank to interleaved 16 bit PCM. `data` is the whole FSB file.
+pub fn decode_sample(fsb: &Fsb5, data: &[u8], index: usize) -> anyhow::Result<Vec<i16>> {
+    let sample = fsb.samples.get(index)
+        .with_context(|| format!("Sample {} does not exist", index))?;
+    let start = (fsb.sample_data_offset() + sample.data_offset) as usize;
+    let end = fsb.samples.get(index + 1)
+        .map(|next| fsb.sample_data_offset() + next.data_offset)
+        .unwrap_or(fsb.sample_data_offset() + fsb.header.sample_data_size as u64) as usize;
+    let sample_data = data.get(start..end.min(data.len()))
+        .context("Sample data is out of bounds")?;
+
+    let channels = sample.channels as usize;
+    let mut pcm = match fsb.header.codec {
+        FsbCodec::Pcm8 => sample_data.iter()
+            .map(|b| (*b as i8 as i16) << 8)
+            .collect(),
+        FsbCodec::Pcm16 => sample_data.chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect(),
+        FsbCodec::Pcm24 => sample_data.chunks_exact(3)
+            .map(|b| i16::from_le_bytes([b[1], b[2]]))
+            .collect(),
+        FsbCodec::Pcm32 => sample_data.chunks_exact(4)
+            .map(|b| i16::from_le_bytes([b[2], b[3]]))
+            .collect(),
+        FsbCodec::PcmFloat => sample_data.chunks_exact(4)
+            .map(|b| (f32::from_le_bytes([b[0], b[1], b[2], b[3]]).clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect(),
+        FsbCodec::ImaAdpcm => decode_ima(sample_data, channels)?,
+        FsbCodec::FAdpcm => decode_fadpcm(sample_data, channels),
+        codec => anyhow::bail!("Decoding {:?} audio is not supported", codec),
+    };
+
+    pcm.truncate(sample.num_samples as usize * channels);
+    Ok(pcm)
+}
+
+/// Decodes Xbox style IMA ADPCM, which FMOD uses for mono and stereo sounds
+fn decode_ima(data: &[u8], channels: usize) -> anyhow::Result<Vec<i16>> {
+    if channels > 2 {
+        anyhow::bail!("Decoding IMA ADPCM with {} channels is not supported", channels);
+    }
+
+    let mut pcm = Vec::new();
+    for block in data.chunks_exact(IMA_BLOCK_SIZE * channels) {
+        let mut state = (0..channels)
+            .map(|c| {
+                let header = &block[c * 4..c * 4 + 4];
+                (i16::from_le_bytes([header[0], header[1]]) as i32, (header[2] as i32).min(88))
+            })
+            .collect::<Vec<_>>();
+
+        // Data of the channels is interleaved in 4 byte words, each holding 8 samples
+        let mut samples = vec![[0i16; 64]; channels];
+        let body = &block[channels * 4..];
+        for (word, bytes) in body.chunks_exact(4).enumerate() {
+            let channel = word % channels;
+            let base = word / channels * 8;
+            for (i, byte) in bytes.iter().enumerate() {
+                for (j, nibble) in [byte & 0xF, byte >> 4].into_iter().enumerate() {
+                    samples[channel][base + i * 2 + j] = ima_step(&mut state[channel], nibble);
+                }
+            }
+        }
+
+        for i in 0..64 {
+            pcm.extend(samples.iter().map(|channel| channel[i]));
+        }
+    }
+    Ok(pcm)
+}
+
+fn ima_step((predictor, index): &mut (i32, i32), nibble: u8) -> i16 {
+    let step = IMA_STEPS[*index as usize];
+    let mut diff = step >> 3;
+    if nibble & 1 != 0 { diff += step >> 2; }
+    if nibble & 2 != 0 { diff += step >> 1; }
+    if nibble & 4 != 0 { diff += step; }
+    if nibble & 8 != 0 { diff = -diff; }
+
+    *predictor = (*predictor + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+    *index = (*index + IMA_INDEX_ADJUST[nibble as usize]).clamp(0, 88);
+    *predictor as i16
+}
+
+/// Decodes FMOD ADPCM. Frames of the channels are interleaved.
+fn decode_fadpcm(data: &[u8], channels: usize) -> Vec<i16> {
+    let mut pcm = Vec::new();
+    for frames in data.chunks_exact(FADPCM_FRAME_SIZE * channels) {
+        let decoded = frames.chunks_exact(FADPCM_FRAME_SIZE)
+            .map(decode_fadpcm_frame)
+            .collect::<Vec<_>>();
+        for i in 0..256 {
+            pcm.extend(decoded.iter().map(|channel| channel[i]));
+        }
+    }
+    pcm
+}
+
+fn decode_fadpcm_frame(frame: &[u8]) -> [i16; 256] {
+    let read_u32 = |offset: usize| u32::from_le_bytes([frame[offset], frame[offset + 1], frame[offset + 2], frame[offset + 3]]);
+    let coefs = read_u32(0);
+    let shifts = read_u32(4);
+    let mut hist1 = i16::from_le_bytes([frame[8], frame[9]]) as i32;
+    let mut hist2 = i16::from_le_bytes([frame[10], frame[11]]) as i32;
+
+    // 8 groups of 32 samples, each with its own predictor and scale
+    let mut out = [0i16; 256];
+    for group in 0..8 {
+        let (coef1, coef2) = FADPCM_COEFS[((coefs >> (group * 4)) & 0x7) as usize];
+        let shift = 22 - ((shifts >> (group * 4)) & 0xF);
+        for word in 0..4 {
+            let value = read_u32(12 + group * 16 + word * 4);
+            for nibble in 0..8 {
+                let sample = ((((value >> (nibble * 4)) & 0xF) << 28) as i32) >> shift;
+                let sample = ((sample - hist2 * coef2 + hist1 * coef1) >> 6)
+                    .clamp(i16::MIN as i32, i16::MAX as i32);
+                out[group * 32 + word * 8 + nibble as usize] = sample as i16;
+                hist2 = hist1;
+                hist1 = sample;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsb::{Fsb5Header, FsbSample};
+
+    /// A sound bank with one sample, followed by its data
+    fn sound_bank(codec: FsbCodec, channels: u32, num_samples: u32, sample_data: &[u8]) -> (Fsb5, Vec<u8>) {
+        let fsb = Fsb5 {
+            header: Fsb5Header {
+                version: 1,
+                num_samples: 1,
+                sample_headers_size: 8,
+                name_table_size: 0,
+                sample_data_size: sample_data.len() as u32,
+                codec,
+                extra: None,
+                reserved: [0; 32],
+            },
+            samples: vec![FsbSample { frequency: 44100, channels, data_offset: 0, num_samples, chunks: Vec::new() }],
+            sample_headers_len: 8,
+        };
+        let mut data = vec![0; fsb.sample_data_offset() as usize];
+        data.extend_from_slice(sample_data);
+        (fsb, data)
+    }
+
+    fn ima_block(predictor: i16, index: u8, body: &[u8]) -> Vec<u8> {
+        let mut block = vec![0; IMA_BLOCK_SIZE];
+        block[..2].copy_from_slice(&predictor.to_le_bytes());
+        block[2] = index;
+        block[4..4 + body.len()].copy_from_slice(body);
+        block
+    }
+
+    #[test]
+    fn decodes_ima_adpcm() {
+        // nibbles are decoded low first: 7, 7, 15, 8, then zeros
+        let block = ima_block(0, 0, &[0x77, 0x8F]);
+        let pcm = decode_ima(&block, 1).unwrap();
+        assert_eq!(pcm.len(), 64);
+        assert_eq!(pcm[..6], [11, 41, -22, -31, -23, -16]);
+    }
+
+    #[test]
+    fn clamps_ima_adpcm() {
+        let block = ima_block(i16::MAX, 88, &[0x07]);
+        assert_eq!(decode_ima(&block, 1).unwrap()[0], i16::MAX);
+        // out of range step indices in the header are clamped as well
+        let block = ima_block(i16::MIN, 200, &[0x0F]);
+        assert_eq!(decode_ima(&block, 1).unwrap()[0], i16::MIN);
+    }
+
+    #[test]
+    fn decodes_interleaved_ima_adpcm_channels() {
+        let mut block = vec![0; IMA_BLOCK_SIZE * 2];
+        block[..2].copy_from_slice(&100i16.to_le_bytes());
+        block[4..6].copy_from_slice(&(-100i16).to_le_bytes());
+        // the second 4 byte word of the body belongs to the second channel
+        block[12] = 0x77;
+        let pcm = decode_ima(&block, 2).unwrap();
+        assert_eq!(pcm.len(), 128);
+        assert_eq!(pcm[..4], [100, -89, 100, -59]);
+        assert!(decode_ima(&block, 3).is_err());
+    }
+
+    fn fadpcm_frame(coefs: u32, shifts: u32, hist: (i16, i16), words: &[(usize, u32)]) -> Vec<u8> {
+        let mut frame = vec![0; FADPCM_FRAME_SIZE];
+        frame[..4].copy_from_slice(&coefs.to_le_bytes());
+        frame[4..8].copy_from_slice(&shifts.to_le_bytes());
+        frame[8..10].copy_from_slice(&hist.0.to_le_bytes());
+        frame[10..12].copy_from_slice(&hist.1.to_le_bytes());
+        for (index, word) in words {
+            frame[12 + index * 4..16 + index * 4].copy_from_slice(&word.to_le_bytes());
+        }
+        frame
+    }
+
+    #[test]
+    fn decodes_fadpcm() {
+        // group 0 predicts from the previous sample, group 1 has a larger scale
+        let frame = fadpcm_frame(0x1, 0x40, (0, 0), &[(0, 0x11), (4, 0xF)]);
+        let pcm = decode_fadpcm(&frame, 1);
+        assert_eq!(pcm.len(), 256);
+        assert_eq!(pcm[..4], [1, 1, 0, 0]);
+        assert_eq!(pcm[32], -16);
+    }
+
+    #[test]
+    fn decodes_fadpcm_with_history() {
+        let frame = fadpcm_frame(0x2, 0, (64, 32), &[]);
+        // (0 - 32 * 60 + 64 * 122) >> 6
+        assert_eq!(decode_fadpcm_frame(&frame)[0], 92);
+    }
+
+    #[test]
+    fn decodes_interleaved_fadpcm_channels() {
+        let mut frames = fadpcm_frame(0, 0, (0, 0), &[(0, 0x7)]);
+        frames.extend(fadpcm_frame(0, 0, (0, 0), &[(0, 0x8)]));
+        let pcm = decode_fadpcm(&frames, 2);
+        assert_eq!(pcm.len(), 512);
+        assert_eq!(pcm[..2], [7, -8]);
+    }
+
+    #[test]
+    fn decode_sample_truncates_to_sample_count() {
+        let (fsb, data) = sound_bank(FsbCodec::ImaAdpcm, 1, 10, &ima_block(0, 0, &[0x77]));
+        let pcm = decode_sample(&fsb, &data, 0).unwrap();
+        assert_eq!(pcm.len(), 10);
+        assert_eq!(pcm[..2], [11, 41]);
+
+        let (fsb, data) = sound_bank(FsbCodec::Pcm8, 1, 2, &[1, 0xFF, 3]);
+        assert_eq!(decode_sample(&fsb, &data, 0).unwrap(), [256, -256]);
+        assert!(decode_sample(&fsb, &data, 1).is_err());
+
+        let (fsb, data) = sound_bank(FsbCodec::Vorbis, 1, 2, &[0; 4]);
+        assert!(decode_sample(&fsb, &data, 0).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{Read, Seek};
+
+use anyhow::Context;
+use binrw::{binrw, BinRead};
+
+pub mod decode;
+pub mod wav;
+
+/// Sample rates that can be stored directly in the sample header. Other rates are stored in a
+/// frequency chunk.
+pub(crate) const FREQUENCIES: [(u64, u32); 11] = [
+    (0, 4000), (1, 8000), (2, 11000), (3, 11025), (4, 16000), (5, 22050),
+    (6, 24000), (7, 32000), (8, 44100), (9, 48000), (10, 96000),
+];
+
+/// Highest FSB5 version known to the FMOD version used by Unity
+const MAX_VERSION: u32 = 1;
+
+pub(crate) const CHUNK_CHANNELS: u32 = 1;
+pub(crate) const CHUNK_FREQUENCY: u32 = 2;
+
+#[binrw]
+#[brw(little, magic = b"FSB5")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fsb5Header {
+    pub version: u32,
+    pub num_samples: u32,
+    pub sample_headers_size: u32,
+    pub name_table_size: u32,
+    pub sample_data_size: u32,
+    pub codec: FsbCodec,
+    #[br(if(version == 0))]
+    #[bw(if(*version == 0))]
+    pub extra: Option<u32>,
+    /// Flags and hash of the sound bank, which aren't used by Unity
+    pub reserved: [u8; 32],
+}
+
+impl Fsb5Header {
+    /// Size of the header itself, which depends on the version
+    pub fn size(&self) -> u64 {
+        if self.version == 0 { 0x40 } else { 0x3C }
+    }
+}
+
+#[binrw]
+#[brw(repr = u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FsbCodec {
+    None = 0,
+    Pcm8 = 1,
+    Pcm16 = 2,
+    Pcm24 = 3,
+    Pcm32 = 4,
+    PcmFloat = 5,
+    GcAdpcm = 6,
+    ImaAdpcm = 7,
+    Vag = 8,
+    HeVag = 9,
+    Xma = 10,
+    Mpeg = 11,
+    Celt = 12,
+    At9 = 13,
+    Xwma = 14,
+    Vorbis = 15,
+    FAdpcm = 16,
+    Opus = 17,
+}
+
+impl FsbCodec {
+    /// Whether the FMOD version bundled with Unity can play this codec on desktop platforms
+    pub fn is_supported(self) -> bool {
+        matches!(self,
+            FsbCodec::Pcm8 | FsbCodec::Pcm16 | FsbCodec::Pcm24 | FsbCodec::Pcm32 | FsbCodec::PcmFloat
+            | FsbCodec::ImaAdpcm | FsbCodec::FAdpcm | FsbCodec::Mpeg | FsbCodec::Vorbis
+        )
+    }
+
+    /// Bits per sample Unity reports for clips in this codec
+    pub fn bits_per_sample(self) -> i32 {
+        match self {
+            FsbCodec::Pcm8 => 8,
+            FsbCodec::Pcm24 => 24,
+            FsbCodec::Pcm32 | FsbCodec::PcmFloat => 32,
+            _ => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsbSample {
+    pub frequency: u32,
+    pub channels: u32,
+    /// Offset of the sample data, relative to the start of the sample data section
+    pub data_offset: u64,
+    /// Number of samples per channel
+    pub num_samples: u32,
+    pub chunks: Vec<FsbChunk>,
+}
+
+impl FsbSample {
+    /// Length of the sample in seconds
+    pub fn length(&self) -> f32 {
+        self.num_samples as f32 / self.frequency as f32
+    }
+}
+
+/// Additional metadata of a sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsbChunk {
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+/// A parsed FSB5 sound bank. Only the headers are read, the sample data stays in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fsb5 {
+    pub header: Fsb5Header,
+    pub samples: Vec<FsbSample>,
+    /// Number of bytes the sample headers actually took up
+    sample_headers_len: u64,
+}
+
+impl Fsb5 {
+    pub fn read<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Self> {
+        let header = Fsb5Header::read(reader)
+            .context("Failed to read FSB5 header")?;
+
+        let start = reader.stream_position()?;
+        let mut samples = Vec::with_capacity(header.num_samples.min(1024) as usize);
+        for index in 0..header.num_samples {
+            let sample = read_sample(reader)
+                .with_context(|| format!("Failed to read header of sample {}", index))?;
+            samples.push(sample);
+        }
+        let sample_headers_len = reader.stream_position()? - start;
+
+        Ok(Self { header, samples, sample_headers_len })
+    }
+
+    /// Checks that the sound bank is well-formed and can be played by the game.
+    /// `file_len` is the size of the whole FSB file.
+    pub fn validate(&self, file_len: u64) -> anyhow::Result<()> {
+        let header = &self.header;
+        if header.version > MAX_VERSION {
+            anyhow::bail!("Unsupported FSB5 version {}", header.version);
+        }
+        if self.samples.is_empty() {
+            anyhow::bail!("Sound bank contains no samples");
+        }
+        if !header.codec.is_supported() {
+            anyhow::bail!("Codec {:?} is not supported by the game", header.codec);
+        }
+        if self.sample_headers_len > header.sample_headers_size as u64 {
+            anyhow::bail!("Sample headers take up {} bytes, but the header declares only {}",
+                self.sample_headers_len, header.sample_headers_size);
+        }
+
+        let expected_len = self.sample_data_offset() + header.sample_data_size as u64;
+        if expected_len > file_len {
+            anyhow::bail!("File is truncated: expected at least {} bytes, but it has {}", expected_len, file_len);
+        }
+
+        for (index, sample) in self.samples.iter().enumerate() {
+            if sample.data_offset >= header.sample_data_size as u64 {
+                anyhow::bail!("Data of sample {} starts outside of the sample data section", index);
+            }
+            if sample.num_samples == 0 {
+                anyhow::bail!("Sample {} is empty", index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Offset of the sample data section from the start of the file
+    pub fn sample_data_offset(&self) -> u64 {
+        self.header.size() + self.header.sample_headers_size as u64 + self.header.name_table_size as u64
+    }
+}
+
+fn read_sample<R: Read + Seek>(reader: &mut R) -> anyhow::Result<FsbSample> {
+    let raw = u64::read_le(reader)?;
+    let mut has_chunk = raw & 1 != 0;
+    let frequency_index = (raw >> 1) & 0xF;
+    let mut sample = FsbSample {
+        frequency: FREQUENCIES.iter()
+            .find(|(index, _)| *index == frequency_index)
+            .map(|(_, frequency)| *frequency)
+            .unwrap_or(0),
+        channels: ((raw >> 5) & 1) as u32 + 1,
+        data_offset: ((raw >> 6) & 0xFFFFFFF) * 16,
+        num_samples: ((raw >> 34) & 0x3FFFFFFF) as u32,
+        chunks: Vec::new(),
+    };
+
+    while has_chunk {
+        let raw = u32::read_le(reader)?;
+        has_chunk = raw & 1 != 0;
+        let size = (raw >> 1) & 0xFFFFFF;
+        let kind = (raw >> 25) & 0x7F;
+        let mut data = vec![0; size as usize];
+        reader.read_exact(&mut data)?;
+
+        match kind {
+            CHUNK_CHANNELS if !data.is_empty() => sample.channels = data[0] as u32,
+            CHUNK_FREQUENCY if data.len() >= 4 => {
+                sample.frequency = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            }
+            _ => {}
+        }
+        sample.chunks.push(FsbChunk { kind, data });
+    }
+
+    if sample.frequency == 0 {
+        anyhow::bail!("Unknown sample rate index {}", frequency_index);
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fsb::wav::encode_pcm16;
+
+    fn read(data: &[u8]) -> Fsb5 {
+        Fsb5::read(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn reads_sample_header() {
+        let data = encode_pcm16(2, 44100, &[0; 40]).unwrap();
+        let fsb = read(&data);
+        assert_eq!(fsb.header.codec, FsbCodec::Pcm16);
+        assert_eq!(fsb.samples.len(), 1);
+        assert_eq!(fsb.samples[0].channels, 2);
+        assert_eq!(fsb.samples[0].frequency, 44100);
+        assert_eq!(fsb.samples[0].num_samples, 10);
+        assert_eq!(fsb.sample_data_offset(), 0x3C + 8);
+        fsb.validate(data.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn reads_channel_and_frequency_chunks() {
+        let data = encode_pcm16(6, 12345, &[0; 24]).unwrap();
+        let fsb = read(&data);
+        assert_eq!(fsb.samples[0].channels, 6);
+        assert_eq!(fsb.samples[0].frequency, 12345);
+        assert_eq!(fsb.samples[0].chunks.len(), 2);
+        fsb.validate(data.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let data = encode_pcm16(1, 44100, &[0; 64]).unwrap();
+        let fsb = read(&data);
+        assert!(fsb.validate(data.len() as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_version_and_codec() {
+        let data = encode_pcm16(1, 44100, &[0; 64]).unwrap();
+        let mut fsb = read(&data);
+        fsb.header.version = 2;
+        assert!(fsb.validate(data.len() as u64).is_err());
+
+        let mut fsb = read(&data);
+        fsb.header.codec = FsbCodec::Celt;
+        assert!(fsb.validate(data.len() as u64).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_sample_headers() {
+        let data = encode_pcm16(1, 44100, &[0; 64]).unwrap();
+        let mut fsb = read(&data);
+        fsb.header.sample_headers_size = 4;
+        assert!(fsb.validate(data.len() as u64).is_err());
+
+        let mut fsb = read(&data);
+        fsb.samples.clear();
+        assert!(fsb.validate(data.len() as u64).is_err());
+
+        let mut fsb = read(&data);
+        fsb.samples[0].data_offset = fsb.header.sample_data_size as u64;
+        assert!(fsb.validate(data.len() as u64).is_err());
+
+        let mut fsb = read(&data);
+        fsb.samples[0].num_samples = 0;
+        assert!(fsb.validate(data.len() as u64).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_sample_rate() {
+        let mut data = encode_pcm16(1, 44100, &[0; 64]).unwrap();
+        // frequency index 15 doesn't exist
+        data[0x3C] |= 0xF << 1;
+        assert!(Fsb5::read(&mut Cursor::new(&data)).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use anyhow::Context;
+use binrw::BinWrite;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use crate::fsb::{Fsb5Header, FsbCodec, CHUNK_CHANNELS, CHUNK_FREQUENCY, FREQUENCIES};
+
+/// Converts a WAV file into a single-sample PCM16 FSB5 sound bank.
+/// Integer samples of other bit depths and float samples are converted to 16 bit.
+pub fn wav_to_fsb<R: Read>(reader: R) -> anyhow::Result<Vec<u8>> {
+    let mut wav = WavReader::new(reader)
+        .context("Failed to read WAV header")?;
+    let spec = wav.spec();
+    if spec.channels == 0 || spec.channels > 255 {
+        anyhow::bail!("Unsupported channel count {}", spec.channels);
+    }
+
+    let samples: Vec<i16> = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, 16) => wav.samples::<i16>().collect::<Result<_, _>>()?,
+        (SampleFormat::Int, bits @ 1..=32) => wav.samples::<i32>()
+            .map(|s| s.map(|s| if bits > 16 { (s >> (bits - 16)) as i16 } else { (s << (16 - bits)) as i16 }))
+            .collect::<Result<_, _>>()?,
+        (SampleFormat::Float, 32) => wav.samples::<f32>()
+            .map(|s| s.map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect::<Result<_, _>>()?,
+        (format, bits) => anyhow::bail!("Unsupported WAV sample format: {} bit {:?}", bits, format),
+    };
+    let samples = samples.iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    encode_pcm16(spec.channels as u32, spec.sample_rate, &samples)
+}
+
+/// Builds an FSB5 sound bank containing one sample of interleaved little endian PCM16 data
+pub fn encode_pcm16(channels: u32, frequency: u32, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let frame_size = channels as usize * 2;
+    let num_samples = (data.len() / frame_size) as u64;
+    if num_samples == 0 {
+        anyhow::bail!("Audio contains no samples");
+    }
+    if num_samples > 0x3FFFFFFF {
+        anyhow::bail!("Audio is too long to fit into an FSB5 sound bank");
+    }
+
+    // Channels and frequencies that don't fit into the sample header are stored in chunks
+    let frequency_index = FREQUENCIES.iter()
+        .find(|(_, f)| *f == frequency)
+        .map(|(index, _)| *index);
+    let mut chunks: Vec<(u32, Vec<u8>)> = Vec::new();
+    if channels > 2 {
+        chunks.push((CHUNK_CHANNELS, vec![channels as u8]));
+    }
+    if frequency_index.is_none() {
+        chunks.push((CHUNK_FREQUENCY, frequency.to_le_bytes().to_vec()));
+    }
+
+    let mut sample_header = Vec::new();
+    let raw = (!chunks.is_empty()) as u64
+        | frequency_index.unwrap_or(0) << 1
+        | ((channels == 2) as u64) << 5
+        | num_samples << 34;
+    sample_header.extend_from_slice(&raw.to_le_bytes());
+    for (i, (kind, chunk)) in chunks.iter().enumerate() {
+        let next = (i + 1 < chunks.len()) as u32;
+        let raw = next | (chunk.len() as u32) << 1 | kind << 25;
+        sample_header.extend_from_slice(&raw.to_le_bytes());
+        sample_header.extend_from_slice(chunk);
+    }
+
+    let data = &data[..num_samples as usize * frame_size];
+    let header = Fsb5Header {
+        version: 1,
+        num_samples: 1,
+        sample_headers_size: sample_header.len() as u32,
+        name_table_size: 0,
+        sample_data_size: data.len().next_multiple_of(32) as u32,
+        codec: FsbCodec::Pcm16,
+        extra: None,
+        reserved: [0; 32],
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    header.write(&mut out)
+        .context("Failed to write FSB5 header")?;
+    let mut out = out.into_inner();
+    out.extend_from_slice(&sample_header);
+    out.extend_from_slice(data);
+    out.resize(out.len() + header.sample_data_size as usize - data.len(), 0);
+    Ok(out)
+}
+
+/// Writes interleaved 16 bit PCM to a WAV file
+pub fn write_wav(path: &Path, channels: u32, frequency: u32, pcm: &[i16]) -> anyhow::Result<()> {
+    let spec = WavSpec {
+        channels: channels as u16,
+        sample_rate: frequency,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    for sample in pcm {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fsb::Fsb5;
+    use crate::fsb::decode::decode_sample;
+
+    fn wav<S: hound::Sample + Copy>(channels: u16, sample_rate: u32, bits: u16, format: SampleFormat, samples: &[S]) -> Vec<u8> {
+        let spec = WavSpec { channels, sample_rate, bits_per_sample: bits, sample_format: format };
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut data, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        data.into_inner()
+    }
+
+    /// Converts a WAV file and decodes it again
+    fn convert(wav: &[u8]) -> (Fsb5, Vec<i16>) {
+        let data = wav_to_fsb(Cursor::new(wav)).unwrap();
+        let fsb = Fsb5::read(&mut Cursor::new(&data)).unwrap();
+        fsb.validate(data.len() as u64).unwrap();
+        let pcm = decode_sample(&fsb, &data, 0).unwrap();
+        (fsb, pcm)
+    }
+
+    #[test]
+    fn converts_pcm16() {
+        let samples = [0i16, 1, -1, i16::MAX, i16::MIN, 1234];
+        let (fsb, pcm) = convert(&wav(2, 22050, 16, SampleFormat::Int, &samples));
+        assert_eq!(fsb.header.codec, FsbCodec::Pcm16);
+        assert_eq!(fsb.samples[0].channels, 2);
+        assert_eq!(fsb.samples[0].frequency, 22050);
+        assert_eq!(fsb.samples[0].num_samples, 3);
+        assert_eq!(fsb.header.sample_data_size % 32, 0);
+        assert_eq!(pcm, samples);
+    }
+
+    #[test]
+    fn converts_other_bit_depths_to_16_bit() {
+        let (_, pcm) = convert(&wav(1, 44100, 8, SampleFormat::Int, &[1i8, -1, 127]));
+        assert_eq!(pcm, [256, -256, 127 << 8]);
+
+        let (_, pcm) = convert(&wav(1, 44100, 24, SampleFormat::Int, &[0x100i32, -0x100, 0x7FFFFF]));
+        assert_eq!(pcm, [1, -1, i16::MAX]);
+
+        let (_, pcm) = convert(&wav(1, 44100, 32, SampleFormat::Float, &[0.5f32, -2.0, 1.0]));
+        assert_eq!(pcm, [i16::MAX / 2, -i16::MAX, i16::MAX]);
+    }
+
+    #[test]
+    fn stores_uncommon_sample_rates_in_a_chunk() {
+        let (fsb, _) = convert(&wav(1, 12345, 16, SampleFormat::Int, &[0i16; 4]));
+        assert_eq!(fsb.samples[0].frequency, 12345);
+    }
+
+    #[test]
+    fn rejects_empty_audio() {
+        let wav = wav::<i16>(1, 44100, 16, SampleFormat::Int, &[]);
+        assert!(wav_to_fsb(Cursor::new(wav)).is_err());
+    }
+}
@@ -4,10 +4,11 @@ use clap::Parser;
 use clap_derive::{Parser, Subcommand, ValueEnum};
 use tracing::{error, info};
 use tracing_subscriber::fmt::time::OffsetTime;
-use crate::command::{pack, patch, revert, unpack};
+use crate::command::{audio, pack, patch, revert, status, unpack};
 
 mod crypto;
 mod command;
+mod fsb;
 mod unity;
 
 #[derive(Debug, Parser)]
@@ -25,6 +26,10 @@ struct Args {
     #[arg(short, long)]
     art_key: Option<String>,
 
+    /// Keep the temporary directory after the command finished, for debugging.
+    #[arg(long)]
+    keep_temp: bool,
+
 }
 
 #[derive(Debug, Subcommand)]
@@ -48,25 +53,75 @@ enum Command {
         /// Output directory.
         #[arg(short, long, default_value = "./out")]
         output: PathBuf,
+
+        /// Also extract all audio clips to FSB files and generate an audio_patches.json template for them.
+        #[arg(long)]
+        audio: bool,
     },
     /// Patch the game files with new/replaced assets from a directory.
     Patch {
-        /// Directory containing assets to insert/replace.
+        /// Directory containing assets to insert/replace. Can be given multiple times to apply several patches in load
+        /// order: files of later patches replace the ones of earlier patches, xml patches are all merged in order and
+        /// audio and sprite patches are combined.
         #[arg(short, long, default_value = "./patch")]
-        patch: PathBuf,
+        patch: Vec<PathBuf>,
 
         /// How should the tool handle localized assets.
         #[arg(long, default_value = "normal")]
         i18n: I18nCompatMode,
+
+        /// Name of the resource file in the game's data folder that patched audio is written to.
+        #[arg(long, default_value = patch::audio_patcher::DEFAULT_RESOURCE_NAME)]
+        resource_name: String,
+
+        /// Apply the patch without touching the game directory and print what would be replaced, added, merged and left untouched.
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+
+        /// Save a JSON report of every file and object the patch replaced, merged or added, with sizes and hashes.
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Keep running and re-apply the patch whenever a file in the patch directory changes.
+        #[arg(long)]
+        watch: bool,
+
+        /// Write the patched game files to this directory instead of the game directory, which is left untouched.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
     /// Reverts the game files to their original state.
     Revert,
+    /// Shows whether the game files are vanilla, patched, or were modified after patching.
+    Status,
+    /// Work with the audio clips of the game.
+    Audio {
+        #[command(subcommand)]
+        command: AudioCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AudioCommand {
+    /// Decode audio clips to WAV files for previewing. Supports PCM and ADPCM audio.
+    Export {
+        /// Input file. Can either be a unity asset bundle or an FSB file. Defaults to the sharedassets0.assets in the game directory.
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+
+        /// Output directory.
+        #[arg(short, long, default_value = "./out/wav")]
+        output: PathBuf,
+
+        /// Names of the audio clips to export. If none are provided, all audio clips are exported.
+        names: Vec<String>,
+    },
 }
 
 impl Command {
     fn needs_key(&self) -> bool {
         match self {
-            Command::Revert => false,
+            Command::Revert | Command::Status | Command::Audio { .. } => false,
             _ => true,
         }
     }
@@ -96,6 +151,9 @@ fn main() {
         ))
         .init();
     let mut args = Args::parse();
+    if let Err(err) = command::workspace::install_interrupt_handler() {
+        error!("{err}");
+    }
     info!("papers-tools v{} by {}", env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_AUTHORS"));
     if args.art_key.is_none() && args.command.needs_key() {
         let res = crypto::extract_key(&args);
@@ -111,15 +169,29 @@ fn main() {
             // unwrap is safe here
             pack::pack(&args.art_key.unwrap(), input, output)
         }
-        Command::Unpack { input, output } => {
-            unpack::unpack(&args, input, output)
+        Command::Unpack { input, output, audio } => {
+            unpack::unpack(&args, input, output, *audio)
         }
-        Command::Patch { patch, i18n } => {
-            patch::patch(&args, patch, i18n)
+        Command::Patch { patch, i18n, resource_name, dry_run, report, watch, output } => {
+            let options = patch::PatchOptions {
+                locale_mode: i18n,
+                resource_name,
+                dry_run: *dry_run,
+                report: report.as_deref(),
+                watch: *watch,
+                output: output.as_deref(),
+            };
+            patch::patch(&args, patch, &options)
         }
         Command::Revert => {
             revert::revert(&args.game_dir)
         }
+        Command::Status => {
+            status::status(&args.game_dir)
+        }
+        Command::Audio { command: AudioCommand::Export { input, output, names } } => {
+            audio::export(&args, input, output, names)
+        }
     };
 
     if let Err(err) = res {
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use binrw::BinRead;
+use binrw::io::BufReader;
+
+use crate::unity::{AssetsFile, ResolvedObjectInfo};
+use crate::unity::sprite::PPtr;
+use crate::unity::util::{AlignedString, AlignmentArgs};
+
+/// Serialized files loaded from a game data folder. Starting from a single assets file, the files
+/// it references through its external file list are loaded on demand, so references to objects
+/// in other files can be resolved.
+pub struct AssetsContext {
+    data_dir: PathBuf,
+    files: Vec<LoadedFile>,
+    /// Index of every loaded file, by its path relative to the data folder (lowercase)
+    by_path: HashMap<String, usize>,
+}
+
+pub struct LoadedFile {
+    /// Path of the file relative to the data folder, as referenced by other files
+    pub name: String,
+    pub path: PathBuf,
+    pub assets: AssetsFile,
+    objects: HashMap<i64, ResolvedObjectInfo>,
+    /// Indices of the loaded external files, in the order of the external file list
+    externals: Vec<Option<usize>>,
+}
+
+/// An object in one of the files of an [AssetsContext]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectRef {
+    pub file: usize,
+    pub path_id: i64,
+    pub class_id: i32,
+}
+
+impl AssetsContext {
+    /// Index of the file the context was loaded from
+    pub const ROOT: usize = 0;
+
+    /// Loads an assets file. Files referenced by it are looked up in the same directory.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data_dir = path.parent()
+            .context("Failed to get directory of assets file")?
+            .to_path_buf();
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .context("Invalid assets file name")?
+            .to_string();
+
+        let mut context = Self { data_dir, files: Vec::new(), by_path: HashMap::new() };
+        context.load_file(name, path.to_path_buf())?;
+        Ok(context)
+    }
+
+    pub fn file(&self, file: usize) -> &LoadedFile {
+        &self.files[file]
+    }
+
+    /// All objects of the given class in a loaded file, ordered by path id
+    pub fn objects_of_class(&self, file: usize, class_id: i32) -> Vec<ObjectRef> {
+        let mut objects = self.files[file].objects.values()
+            .filter(|obj| obj.class_id == class_id)
+            .map(|obj| ObjectRef { file, path_id: obj.path_id, class_id })
+            .collect::<Vec<_>>();
+        objects.sort_by_key(|obj| obj.path_id);
+        objects
+    }
+
+    /// Resolves a reference from an object in the given file. Returns `None` for null references.
+    pub fn resolve(&mut self, file: usize, pptr: PPtr) -> anyhow::Result<Option<ObjectRef>> {
+        if pptr.path_id == 0 {
+            return Ok(None);
+        }
+
+        let target = if pptr.file_id == 0 {
+            file
+        } else {
+            self.external(file, pptr.file_id as usize - 1)?
+        };
+        let loaded = &self.files[target];
+        let obj = loaded.objects.get(&pptr.path_id)
+            .with_context(|| format!("Object with path id {} does not exist in {}", pptr.path_id, loaded.name))?;
+        Ok(Some(ObjectRef { file: target, path_id: obj.path_id, class_id: obj.class_id }))
+    }
+
+    /// Reads a resolved object
+    pub fn read_object<T: BinRead>(&self, obj: ObjectRef, args: T::Args<'_>) -> anyhow::Result<T> {
+        let loaded = &self.files[obj.file];
+        let info = &loaded.objects[&obj.path_id];
+        let mut input = BufReader::new(File::open(&loaded.path)
+            .with_context(|| format!("Failed to open {}", loaded.path.display()))?);
+        input.seek(SeekFrom::Start(loaded.assets.header.offset_first_file + info.byte_start))
+            .context("Failed to seek to object")?;
+        let mut data = vec![0; info.byte_size as usize];
+        input.read_exact(&mut data)
+            .context("Failed to read object data")?;
+        let object = T::read_options(&mut Cursor::new(data), loaded.assets.endian(), args)
+            .with_context(|| format!("Failed to read object with path id {} in {}", obj.path_id, loaded.name))?;
+        Ok(object)
+    }
+
+    /// Reads the name of a resolved object. Only works for objects that start with a name, which is
+    /// the case for all assets (textures, sprites, audio clips, etc.).
+    pub fn read_object_name(&self, obj: ObjectRef) -> anyhow::Result<String> {
+        self.read_object::<AlignedString>(obj, AlignmentArgs::new(4))
+            .map(|name| name.0)
+    }
+
+    /// Returns the index of an external file of the given file, loading it if needed
+    fn external(&mut self, file: usize, index: usize) -> anyhow::Result<usize> {
+        let loaded = &self.files[file];
+        if let Some(Some(external)) = loaded.externals.get(index) {
+            return Ok(*external);
+        }
+
+        let identifier = loaded.assets.content.externals.get(index)
+            .with_context(|| format!("External file {} does not exist in {}", index + 1, loaded.name))?;
+        let name = identifier.path.to_string();
+        let external = match self.by_path.get(&name.to_lowercase()) {
+            Some(external) => *external,
+            None => {
+                let path = self.find_file(&name)
+                    .with_context(|| format!("Couldn't find {} referenced by {}", name, loaded.name))?;
+                self.load_file(name, path)?
+            }
+        };
+
+        self.files[file].externals[index] = Some(external);
+        Ok(external)
+    }
+
+    /// Finds a file referenced in an external file list. Built-in resources are referenced with a
+    /// `library/` prefix, but are stored in the `Resources` directory of the data folder.
+    fn find_file(&self, name: &str) -> Option<PathBuf> {
+        let mut candidates = vec![self.data_dir.join(name)];
+        let stripped = ["library/", "resources/"].iter().find_map(|prefix| {
+            name.get(..prefix.len())
+                .filter(|start| start.eq_ignore_ascii_case(prefix))
+                .map(|_| &name[prefix.len()..])
+        });
+        if let Some(stripped) = stripped {
+            candidates.push(self.data_dir.join("Resources").join(stripped));
+        }
+        candidates.into_iter().find(|path| path.is_file())
+    }
+
+    fn load_file(&mut self, name: String, path: PathBuf) -> anyhow::Result<usize> {
+        let mut input = BufReader::new(File::open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?);
+        let assets = AssetsFile::read(&mut input)
+            .with_context(|| format!("Failed to read assets file {}", path.display()))?;
+        let objects = assets.resolve_object_classes()?
+            .into_iter()
+            .map(|obj| (obj.path_id, obj))
+            .collect();
+        let externals = vec![None; assets.content.externals.len()];
+
+        let index = self.files.len();
+        self.by_path.insert(name.to_lowercase(), index);
+        self.files.push(LoadedFile { name, path, assets, objects, externals });
+        Ok(index)
+    }
+}
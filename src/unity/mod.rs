@@ -1,16 +1,27 @@
-use binrw::{binrw, NullString};
+use std::io::Cursor;
 
-use crate::unity::util::{Endian, U8Bool};
+use anyhow::Context;
+use binrw::{binrw, BinWrite, Endian as BinrwEndian, NullString};
+use binrw::__private::write_zeroes;
+
+use crate::unity::util::{Endian, U8Bool, UnityVersion};
 
 pub mod util;
 pub mod audio;
+pub mod context;
+pub mod resource;
+pub mod sprite;
+pub mod text;
+pub mod texture;
 
+pub const TEXTURE_2D_CLASS: i32 = 28;
 pub const TEXT_ASSET_CLASS: i32 = 49;
 pub const AUDIO_CLIP_CLASS: i32 = 83;
+pub const SPRITE_CLASS: i32 = 213;
 
 #[binrw]
 #[brw(big)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct AssetsFile {
     #[brw(big)]
     pub header: AssetsFileHeader,
@@ -39,11 +50,58 @@ impl AssetsFile {
     pub fn endian<T>(&self) -> T where Endian: Into<T> {
         self.header.endianness.clone().into()
     }
+
+    pub fn unity_version(&self) -> anyhow::Result<UnityVersion> {
+        UnityVersion::parse(&self.content.unity_version.to_string())
+    }
+
+    /// Returns the path id that follows the highest path id currently used in this file.
+    pub fn next_path_id(&self) -> i64 {
+        self.content.objects.iter()
+            .map(|obj| obj.path_id)
+            .max()
+            .unwrap_or(0) + 1
+    }
+}
+
+impl AssetsFileContent {
+    /// Returns the index of the type entry for the given class, registering a new entry if no
+    /// object of this class exists in the file yet.
+    ///
+    /// The type hash of newly registered types is left empty, as it can't be derived without the
+    /// type tree. Unity doesn't verify it for player builds.
+    pub fn type_index(&mut self, class_id: i32) -> i32 {
+        if let Some(index) = self.types.iter().position(|t| t.class_id == class_id) {
+            return index as i32;
+        }
+
+        self.types.push(SerializedType {
+            class_id,
+            is_stripped_type: U8Bool(false),
+            script_type_index: u16::MAX,
+            script_id: None,
+            old_type_hash: [0; 16],
+        });
+        (self.types.len() - 1) as i32
+    }
+}
+
+/// Serializes an object into the layout it has in the object data section of an assets file. The
+/// returned data is padded to the next 4-byte boundary, just like Unity does.
+pub fn serialize_object<'a, T: BinWrite>(object: &T, endian: BinrwEndian, args: T::Args<'a>) -> anyhow::Result<Vec<u8>> {
+    let mut writer = Cursor::new(Vec::new());
+    object.write_options(&mut writer, endian, args)
+        .context("Failed to serialize object")?;
+    let rem = writer.position() % 4;
+    if rem != 0 {
+        write_zeroes(&mut writer, 4 - rem)?;
+    }
+    Ok(writer.into_inner())
 }
 
 #[binrw]
 #[brw(big)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct AssetsFileHeader {
     #[br(assert(version == 22))]
     #[brw(pad_before = 8, pad_after = 4)]
@@ -56,7 +114,7 @@ pub struct AssetsFileHeader {
 }
 
 #[binrw]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct AssetsFileContent {
     pub unity_version: NullString,
     pub target: u32,
@@ -86,7 +144,7 @@ pub struct AssetsFileContent {
 }
 
 #[binrw]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct SerializedType {
     pub class_id: i32,
     pub is_stripped_type: U8Bool,
@@ -97,7 +155,7 @@ pub struct SerializedType {
 }
 
 #[binrw]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ScriptType {
     local_serialized_file_index: i32,
     #[brw(align_before(4))]
@@ -114,7 +172,7 @@ pub struct FileIdentifier {
 }
 
 #[binrw]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ObjectInfo {
     #[brw(align_before(4))]
     pub path_id: i64,
@@ -123,10 +181,54 @@ pub struct ObjectInfo {
     pub type_id: i32,
 }
 
+/// An object that is appended to an assets file when repacking it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NewObject {
+    pub path_id: i64,
+    pub class_id: i32,
+    /// Name the object was added under, so it can be replaced or removed again
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct ResolvedObjectInfo {
     pub path_id: i64,
     pub byte_start: u64,
     pub byte_size: u32,
     pub class_id: i32,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unity::text::TextAsset;
+    use crate::unity::util::AlignedString;
+
+    #[test]
+    fn serialize_object_pads_to_four_bytes() {
+        let data = serialize_object(&[1u8, 2, 3, 4, 5], BinrwEndian::Little, ()).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 0, 0, 0]);
+
+        // already aligned data isn't padded
+        let data = serialize_object(&0x01020304u32, BinrwEndian::Little, ()).unwrap();
+        assert_eq!(data, [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn serialize_object_honors_endianness() {
+        let data = serialize_object(&[0x0102u16, 0x0304], BinrwEndian::Big, ()).unwrap();
+        assert_eq!(data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn serialized_text_asset_matches_header_len() {
+        let text_asset = TextAsset { object_name: AlignedString("abcde".to_string()), script: vec![1, 2, 3] };
+        let data = serialize_object(&text_asset, BinrwEndian::Little, ()).unwrap();
+        let header_len = TextAsset::header_len("abcde") as usize;
+        assert_eq!(header_len, 16);
+        assert_eq!(&data[..4], &5u32.to_le_bytes());
+        assert_eq!(&data[4..9], b"abcde");
+        assert_eq!(&data[header_len - 4..header_len], &3u32.to_le_bytes());
+        assert_eq!(&data[header_len..], [1, 2, 3, 0]);
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Take};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use binrw::io::BufReader;
+
+use crate::unity::audio::StreamedResource;
+use crate::unity::texture::StreamingInfo;
+
+/// Location of object data that is stored in a resource file (`.resS` or `.resource`) instead of
+/// the assets file itself
+pub trait ResourceLocation {
+    /// Path of the resource file as stored in the object
+    fn source(&self) -> &str;
+    fn offset(&self) -> u64;
+    fn size(&self) -> u64;
+}
+
+impl ResourceLocation for StreamedResource {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset as u64
+    }
+
+    fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+impl ResourceLocation for StreamingInfo {
+    fn source(&self) -> &str {
+        &self.path
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+/// Maps resource locations of objects in an assets file to the resource files next to it.
+pub struct ResourceResolver {
+    dir: PathBuf,
+}
+
+impl ResourceResolver {
+    /// Creates a resolver for the objects of the given assets file
+    pub fn new(assets_path: &Path) -> anyhow::Result<Self> {
+        let dir = assets_path.parent()
+            .context("Failed to get directory of assets file")?
+            .to_path_buf();
+        Ok(Self { dir })
+    }
+
+    /// Returns the path of the resource file a location points to. Resources of asset bundles are
+    /// referenced as `archive:/<bundle>/<file>`, in which case only the file name is used.
+    pub fn path(&self, location: &impl ResourceLocation) -> PathBuf {
+        let source = location.source();
+        match source.strip_prefix("archive:/") {
+            Some(archived) => self.dir.join(archived.rsplit('/').next().unwrap_or(archived)),
+            None => self.dir.join(source),
+        }
+    }
+
+    /// Makes sure the resource file exists and contains the whole range of the location
+    pub fn check(&self, location: &impl ResourceLocation) -> anyhow::Result<()> {
+        let path = self.path(location);
+        let len = std::fs::metadata(&path)
+            .with_context(|| format!("Couldn't find resource file {}", path.display()))?
+            .len();
+        let end = location.offset() + location.size();
+        if end > len {
+            anyhow::bail!("Resource range {}..{} exceeds the size of {} ({} bytes)",
+                location.offset(), end, path.display(), len);
+        }
+        Ok(())
+    }
+
+    /// Opens the resource file and returns a reader limited to the data of the location
+    pub fn open(&self, location: &impl ResourceLocation) -> anyhow::Result<Take<BufReader<File>>> {
+        self.check(location)?;
+        let path = self.path(location);
+        let mut reader = BufReader::new(File::open(&path)
+            .with_context(|| format!("Failed to open resource file {}", path.display()))?);
+        reader.seek(SeekFrom::Start(location.offset()))
+            .context("Failed to seek to resource data")?;
+        Ok(reader.take(location.size()))
+    }
+
+    /// Reads the data of the location into memory
+    pub fn read(&self, location: &impl ResourceLocation) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(location.size() as usize);
+        self.open(location)?.read_to_end(&mut data)
+            .context("Failed to read resource data")?;
+        Ok(data)
+    }
+}
@@ -0,0 +1,280 @@
+use binrw::{binrw, Endian};
+use binrw::helpers::until_eof;
+use serde::{Deserialize, Serialize};
+
+use crate::unity::util::{AlignedString, ByteArray, U8Bool};
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    pub object_name: AlignedString,
+    pub rect: Rectf,
+    pub offset: Vector2f,
+    pub border: Vector4f,
+    pub pixels_to_units: f32,
+    pub pivot: Vector2f,
+    pub extrude: u32,
+    pub is_polygon: U8Bool,
+    #[brw(align_before = 4)]
+    pub render_data_key: ([u8; 16], i64),
+    #[bw(calc = atlas_tags.len() as u32)]
+    atlas_tag_count: u32,
+    #[br(count = atlas_tag_count)]
+    pub atlas_tags: Vec<AlignedString>,
+    pub sprite_atlas: PPtr,
+    pub render_data: SpriteRenderData,
+    /// Physics shape and bones, which are kept as is
+    #[br(parse_with = until_eof)]
+    pub remainder: Vec<u8>,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct SpriteRenderData {
+    pub texture: PPtr,
+    pub alpha_texture: PPtr,
+    #[bw(calc = secondary_textures.len() as u32)]
+    secondary_texture_count: u32,
+    #[br(count = secondary_texture_count)]
+    pub secondary_textures: Vec<SecondarySpriteTexture>,
+    #[bw(calc = sub_meshes.len() as u32)]
+    sub_mesh_count: u32,
+    #[br(count = sub_mesh_count)]
+    pub sub_meshes: Vec<SubMesh>,
+    pub index_buffer: ByteArray,
+    pub vertex_data: VertexData,
+    #[bw(calc = bind_pose.len() as u32)]
+    bind_pose_count: u32,
+    #[br(count = bind_pose_count)]
+    pub bind_pose: Vec<[f32; 16]>,
+    pub texture_rect: Rectf,
+    pub texture_rect_offset: Vector2f,
+    pub atlas_rect_offset: Vector2f,
+    pub settings_raw: u32,
+    pub uv_transform: Vector4f,
+    pub downscale_multiplier: f32,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct SecondarySpriteTexture {
+    pub texture: PPtr,
+    pub name: AlignedString,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct SubMesh {
+    pub first_byte: u32,
+    pub index_count: u32,
+    pub topology: i32,
+    pub base_vertex: u32,
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub local_aabb_center: [f32; 3],
+    pub local_aabb_extent: [f32; 3],
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct VertexData {
+    pub vertex_count: u32,
+    #[bw(calc = channels.len() as u32)]
+    channel_count: u32,
+    #[br(count = channel_count)]
+    pub channels: Vec<ChannelInfo>,
+    pub data: ByteArray,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    pub stream: u8,
+    pub offset: u8,
+    pub format: u8,
+    pub dimension: u8,
+}
+
+/// Reference to an object, either in the same file (file id 0) or in one of its external files.
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PPtr {
+    pub file_id: i32,
+    pub path_id: i64,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rectf {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Vector2f {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Vector4f {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+/// Channel index of vertex positions
+const POSITION_CHANNEL: usize = 0;
+/// Channel index of the first set of texture coordinates
+const UV_CHANNEL: usize = 4;
+/// Vertex format id of 32-bit floats
+const FLOAT_FORMAT: u8 = 0;
+
+impl Sprite {
+    /// Whether the sprite is packed into an atlas texture
+    pub fn is_packed(&self) -> bool {
+        self.render_data.settings_raw & 1 != 0
+    }
+
+    /// Moves and resizes the sprite within its texture, and changes its pivot. The mesh of the
+    /// sprite is transformed to match, so it keeps covering the same relative area of the rect.
+    /// `texture_size` is the size of the texture the sprite is on, after any changes to it, and
+    /// `endian` the byte order of the assets file the vertex data is from.
+    pub fn set_layout(&mut self, rect: Rectf, pivot: Vector2f, texture_size: (f32, f32), endian: Endian) -> anyhow::Result<()> {
+        if self.is_packed() {
+            anyhow::bail!("Changing the layout of sprites that are packed into an atlas is not supported");
+        }
+        if rect.width <= 0.0 || rect.height <= 0.0 {
+            anyhow::bail!("Sprite rect must have a positive size");
+        }
+
+        let old_rect = self.rect;
+        let old_pivot = self.pivot;
+        let ppu = self.pixels_to_units;
+        let scale = (rect.width / old_rect.width, rect.height / old_rect.height);
+
+        // Maps a point in pixels relative to the bottom left of the old rect to the new rect
+        let map_pixel = |x: f32, y: f32| (x * scale.0, y * scale.1);
+
+        let positions = self.render_data.vertex_data.channel_offsets(POSITION_CHANNEL)?;
+        let uvs = self.render_data.vertex_data.channel_offsets(UV_CHANNEL)?;
+        let data = &mut self.render_data.vertex_data.data.data;
+        let mut min = (f32::MAX, f32::MAX);
+        let mut max = (f32::MIN, f32::MIN);
+        for (index, pos_offset) in positions.iter().enumerate() {
+            let x = read_f32(data, *pos_offset, endian);
+            let y = read_f32(data, pos_offset + 4, endian);
+            let (px, py) = map_pixel(
+                x * ppu + old_pivot.x * old_rect.width,
+                y * ppu + old_pivot.y * old_rect.height,
+            );
+            let new_x = (px - pivot.x * rect.width) / ppu;
+            let new_y = (py - pivot.y * rect.height) / ppu;
+            write_f32(data, *pos_offset, new_x, endian);
+            write_f32(data, pos_offset + 4, new_y, endian);
+            min = (min.0.min(new_x), min.1.min(new_y));
+            max = (max.0.max(new_x), max.1.max(new_y));
+
+            if let Some(uv_offset) = uvs.get(index) {
+                write_f32(data, *uv_offset, (rect.x + px) / texture_size.0, endian);
+                write_f32(data, uv_offset + 4, (rect.y + py) / texture_size.1, endian);
+            }
+        }
+
+        if !positions.is_empty() {
+            for sub_mesh in &mut self.render_data.sub_meshes {
+                sub_mesh.local_aabb_center[0] = (min.0 + max.0) / 2.0;
+                sub_mesh.local_aabb_center[1] = (min.1 + max.1) / 2.0;
+                sub_mesh.local_aabb_extent[0] = (max.0 - min.0) / 2.0;
+                sub_mesh.local_aabb_extent[1] = (max.1 - min.1) / 2.0;
+            }
+        }
+
+        let render_data = &mut self.render_data;
+        let texture_rect_offset = render_data.texture_rect_offset;
+        let (offset_x, offset_y) = map_pixel(texture_rect_offset.x, texture_rect_offset.y);
+        let (width, height) = map_pixel(render_data.texture_rect.width, render_data.texture_rect.height);
+        render_data.texture_rect_offset = Vector2f { x: offset_x, y: offset_y };
+        render_data.texture_rect = Rectf { x: rect.x + offset_x, y: rect.y + offset_y, width, height };
+
+        let old_pivot_px = (old_pivot.x * old_rect.width, old_pivot.y * old_rect.height);
+        let new_pivot_px = (pivot.x * rect.width, pivot.y * rect.height);
+        render_data.uv_transform.y += new_pivot_px.0 - old_pivot_px.0;
+        render_data.uv_transform.w += new_pivot_px.1 - old_pivot_px.1;
+        self.offset.x += (rect.width / 2.0 - new_pivot_px.0) - (old_rect.width / 2.0 - old_pivot_px.0);
+        self.offset.y += (rect.height / 2.0 - new_pivot_px.1) - (old_rect.height / 2.0 - old_pivot_px.1);
+
+        self.rect = rect;
+        self.pivot = pivot;
+        Ok(())
+    }
+}
+
+impl VertexData {
+    /// Returns the offsets of the given channel of every vertex in the vertex data. The channel has
+    /// to consist of at least two 32-bit floats. An empty list is returned if the channel isn't used.
+    fn channel_offsets(&self, channel: usize) -> anyhow::Result<Vec<usize>> {
+        let info = match self.channels.get(channel) {
+            Some(info) if info.dimension & 0xF != 0 => info,
+            _ => return Ok(Vec::new()),
+        };
+        if info.format != FLOAT_FORMAT || info.dimension & 0xF < 2 {
+            anyhow::bail!("Unsupported vertex format {} for channel {}", info.format, channel);
+        }
+
+        // Streams are stored one after another, each aligned to 16 bytes
+        let vertex_count = self.vertex_count as usize;
+        let mut stream_start = 0;
+        for stream in 0..=info.stream {
+            let stride = self.stream_stride(stream)?;
+            if stream == info.stream {
+                let offsets = (0..vertex_count)
+                    .map(|vertex| stream_start + vertex * stride + info.offset as usize)
+                    .collect::<Vec<_>>();
+                if offsets.last().is_some_and(|last| last + 8 > self.data.data.len()) {
+                    anyhow::bail!("Vertex data is too short");
+                }
+                return Ok(offsets);
+            }
+            stream_start = (stream_start + vertex_count * stride).div_ceil(16) * 16;
+        }
+        unreachable!()
+    }
+
+    /// Returns the size of a single vertex in the given stream
+    fn stream_stride(&self, stream: u8) -> anyhow::Result<usize> {
+        let mut stride = 0;
+        for info in self.channels.iter().filter(|c| c.stream == stream && c.dimension & 0xF != 0) {
+            let component_size = match info.format {
+                0 | 10 | 11 => 4, // float, uint32, sint32
+                1 | 4 | 5 | 8 | 9 => 2, // half, unorm16, snorm16, uint16, sint16
+                2 | 3 | 6 | 7 => 1, // unorm8, snorm8, uint8, sint8
+                format => anyhow::bail!("Unknown vertex format {}", format),
+            };
+            stride += component_size * (info.dimension & 0xF) as usize;
+        }
+        Ok(stride)
+    }
+}
+
+fn read_f32(data: &[u8], offset: usize, endian: Endian) -> f32 {
+    let bytes = data[offset..offset + 4].try_into().unwrap();
+    match endian {
+        Endian::Big => f32::from_be_bytes(bytes),
+        Endian::Little => f32::from_le_bytes(bytes),
+    }
+}
+
+fn write_f32(data: &mut [u8], offset: usize, value: f32, endian: Endian) {
+    let bytes = match endian {
+        Endian::Big => value.to_be_bytes(),
+        Endian::Little => value.to_le_bytes(),
+    };
+    data[offset..offset + 4].copy_from_slice(&bytes);
+}
@@ -0,0 +1,23 @@
+use binrw::binrw;
+
+use crate::unity::util::AlignedString;
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct TextAsset {
+    pub object_name: AlignedString,
+    #[bw(calc = script.len() as u32)]
+    script_len: u32,
+    #[br(count = script_len)]
+    #[brw(align_after = 4)]
+    pub script: Vec<u8>,
+}
+
+impl TextAsset {
+    /// Returns the length of a serialized TextAsset with the given name up to the start of its
+    /// script data. This consists of the object name (length prefixed and aligned to 4 bytes) and
+    /// the length of the script data.
+    pub fn header_len(object_name: &str) -> u64 {
+        4 + (object_name.len() as u64).div_ceil(4) * 4 + 4
+    }
+}
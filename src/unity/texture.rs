@@ -0,0 +1,319 @@
+use binrw::binrw;
+
+use crate::unity::util::{AlignedString, ByteArray, U8Bool, UnityVersion};
+
+#[binrw]
+#[brw(import(version: UnityVersion))]
+#[derive(Debug, Clone)]
+pub struct Texture2D {
+    pub object_name: AlignedString,
+    pub forced_fallback_format: i32,
+    pub downscale_fallback: U8Bool,
+    #[brw(if(version >= UnityVersion(2020, 2)))]
+    pub is_alpha_channel_optional: Option<U8Bool>,
+    #[brw(align_before = 4)]
+    pub width: i32,
+    pub height: i32,
+    pub complete_image_size: u32,
+    pub mips_stripped: i32,
+    pub texture_format: TextureFormat,
+    pub mip_count: i32,
+    pub is_readable: U8Bool,
+    pub is_pre_processed: U8Bool,
+    pub ignore_mipmap_limit: U8Bool,
+    #[brw(if(version >= UnityVersion(2022, 2)))]
+    pub mipmap_limit_group_name: Option<MipmapLimitGroupName>,
+    pub streaming_mipmaps: U8Bool,
+    #[brw(align_before = 4)]
+    pub streaming_mipmaps_priority: i32,
+    pub image_count: i32,
+    pub texture_dimension: i32,
+    pub texture_settings: GLTextureSettings,
+    pub lightmap_format: i32,
+    pub color_space: i32,
+    #[brw(if(version >= UnityVersion(2020, 2)))]
+    pub platform_blob: Option<ByteArray>,
+    pub image_data: ByteArray,
+    pub stream_data: StreamingInfo,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct MipmapLimitGroupName {
+    #[brw(align_before = 4)]
+    pub name: AlignedString,
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct GLTextureSettings {
+    pub filter_mode: i32,
+    pub aniso: i32,
+    pub mip_bias: f32,
+    pub wrap_u: i32,
+    pub wrap_v: i32,
+    pub wrap_w: i32,
+}
+
+/// Location of data that is stored outside the assets file, usually in a `.resS` file next to it.
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct StreamingInfo {
+    pub offset: u64,
+    pub size: u32,
+    pub path: AlignedString,
+}
+
+#[binrw]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TextureFormat(pub i32);
+
+impl TextureFormat {
+    pub const ALPHA8: Self = Self(1);
+    pub const ARGB4444: Self = Self(2);
+    pub const RGB24: Self = Self(3);
+    pub const RGBA32: Self = Self(4);
+    pub const ARGB32: Self = Self(5);
+    pub const RGB565: Self = Self(7);
+    pub const R16: Self = Self(9);
+    pub const DXT1: Self = Self(10);
+    pub const DXT5: Self = Self(12);
+    pub const RGBA4444: Self = Self(13);
+    pub const BGRA32: Self = Self(14);
+    pub const BC7: Self = Self(25);
+    pub const BC4: Self = Self(26);
+    pub const BC5: Self = Self(27);
+    pub const ETC_RGB4: Self = Self(34);
+    pub const ETC2_RGB: Self = Self(45);
+    pub const ETC2_RGBA1: Self = Self(46);
+    pub const ETC2_RGBA8: Self = Self(47);
+    pub const RG16: Self = Self(62);
+    pub const R8: Self = Self(63);
+
+    /// Returns the size of a single mip level with the given dimensions in bytes, or `None` if the
+    /// format isn't supported or the size doesn't fit in memory.
+    pub fn level_size(self, width: usize, height: usize) -> Option<usize> {
+        let pixels = width.checked_mul(height)?;
+        let blocks = width.div_ceil(4).checked_mul(height.div_ceil(4))?;
+        match self {
+            Self::ALPHA8 | Self::R8 => Some(pixels),
+            Self::ARGB4444 | Self::RGBA4444 | Self::RGB565 | Self::R16 | Self::RG16 => pixels.checked_mul(2),
+            Self::RGB24 => pixels.checked_mul(3),
+            Self::RGBA32 | Self::ARGB32 | Self::BGRA32 => pixels.checked_mul(4),
+            Self::DXT1 | Self::BC4 | Self::ETC_RGB4 | Self::ETC2_RGB | Self::ETC2_RGBA1 => blocks.checked_mul(8),
+            Self::DXT5 | Self::BC7 | Self::BC5 | Self::ETC2_RGBA8 => blocks.checked_mul(16),
+            _ => None,
+        }
+    }
+
+    /// Decodes a single mip level into RGBA pixels. Rows are kept in the order they're stored in,
+    /// which is bottom to top for Unity textures.
+    pub fn decode(self, data: &[u8], width: usize, height: usize) -> anyhow::Result<Vec<u8>> {
+        if width == 0 || height == 0 {
+            anyhow::bail!("Invalid texture dimensions {}x{}", width, height);
+        }
+        let size = self.level_size(width, height)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported texture format {} or size {}x{}", self.0, width, height))?;
+        if data.len() < size {
+            anyhow::bail!("Texture data is too short. Expected {} bytes, got {}", size, data.len());
+        }
+        let data = &data[..size];
+
+        let pixels = match self {
+            Self::ALPHA8 => data.iter().flat_map(|&a| [255, 255, 255, a]).collect(),
+            Self::R8 => data.iter().flat_map(|&r| [r, 0, 0, 255]).collect(),
+            Self::R16 => data.chunks_exact(2).flat_map(|p| [p[1], 0, 0, 255]).collect(),
+            Self::RG16 => data.chunks_exact(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+            Self::RGB24 => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            Self::RGBA32 => data.to_vec(),
+            Self::ARGB32 => data.chunks_exact(4).flat_map(|p| [p[1], p[2], p[3], p[0]]).collect(),
+            Self::BGRA32 => data.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect(),
+            Self::RGB565 => data.chunks_exact(2)
+                .map(|p| u16::from_le_bytes([p[0], p[1]]))
+                .flat_map(|v| {
+                    let r = ((v >> 11) & 0x1F) as u8;
+                    let g = ((v >> 5) & 0x3F) as u8;
+                    let b = (v & 0x1F) as u8;
+                    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2), 255]
+                })
+                .collect(),
+            Self::ARGB4444 => data.chunks_exact(2)
+                .map(|p| u16::from_le_bytes([p[0], p[1]]))
+                .flat_map(|v| [(v >> 8) & 0xF, (v >> 4) & 0xF, v & 0xF, (v >> 12) & 0xF].map(|c| c as u8 * 17))
+                .collect(),
+            Self::RGBA4444 => data.chunks_exact(2)
+                .map(|p| u16::from_le_bytes([p[0], p[1]]))
+                .flat_map(|v| [(v >> 12) & 0xF, (v >> 8) & 0xF, (v >> 4) & 0xF, v & 0xF].map(|c| c as u8 * 17))
+                .collect(),
+            _ => {
+                let decode = match self {
+                    Self::DXT1 => texture2ddecoder::decode_bc1,
+                    Self::DXT5 => texture2ddecoder::decode_bc3,
+                    Self::BC4 => texture2ddecoder::decode_bc4,
+                    Self::BC5 => texture2ddecoder::decode_bc5,
+                    Self::BC7 => texture2ddecoder::decode_bc7,
+                    Self::ETC_RGB4 => texture2ddecoder::decode_etc1,
+                    Self::ETC2_RGB => texture2ddecoder::decode_etc2_rgb,
+                    Self::ETC2_RGBA1 => texture2ddecoder::decode_etc2_rgba1,
+                    Self::ETC2_RGBA8 => texture2ddecoder::decode_etc2_rgba8,
+                    _ => anyhow::bail!("Unsupported texture format: {}", self.0),
+                };
+                let mut image = vec![0u32; width * height];
+                decode(data, width, height, &mut image)
+                    .map_err(|e| anyhow::anyhow!("Failed to decode texture: {}", e))?;
+                // the decoder returns BGRA pixels
+                image.iter()
+                    .map(|p| p.to_le_bytes())
+                    .flat_map(|[b, g, r, a]| [r, g, b, a])
+                    .collect()
+            }
+        };
+
+        Ok(pixels)
+    }
+}
+
+impl TextureFormat {
+    /// Encodes RGBA pixels into this format. Only uncompressed formats are supported.
+    pub fn encode(self, pixels: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let rgba = pixels.chunks_exact(4);
+        let data = match self {
+            Self::ALPHA8 => rgba.map(|p| p[3]).collect(),
+            Self::R8 => rgba.map(|p| p[0]).collect(),
+            Self::RGB24 => rgba.flat_map(|p| [p[0], p[1], p[2]]).collect(),
+            Self::RGBA32 => pixels.to_vec(),
+            Self::ARGB32 => rgba.flat_map(|p| [p[3], p[0], p[1], p[2]]).collect(),
+            Self::BGRA32 => rgba.flat_map(|p| [p[2], p[1], p[0], p[3]]).collect(),
+            Self::RGB565 => rgba
+                .map(|p| ((p[0] as u16 >> 3) << 11) | ((p[1] as u16 >> 2) << 5) | (p[2] as u16 >> 3))
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            Self::ARGB4444 => rgba
+                .map(|p| [p[3], p[0], p[1], p[2]].iter().fold(0u16, |v, &c| (v << 4) | (c as u16 >> 4)))
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            Self::RGBA4444 => rgba
+                .map(|p| p.iter().fold(0u16, |v, &c| (v << 4) | (c as u16 >> 4)))
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            _ => anyhow::bail!("Encoding textures to format {} is not supported", self.0),
+        };
+        Ok(data)
+    }
+
+    /// Whether textures can be encoded to this format
+    pub fn is_encodable(self) -> bool {
+        matches!(self, Self::ALPHA8 | Self::R8 | Self::RGB24 | Self::RGBA32 | Self::ARGB32 | Self::BGRA32
+            | Self::RGB565 | Self::ARGB4444 | Self::RGBA4444)
+    }
+}
+
+impl Texture2D {
+    /// Creates an empty texture with the import settings Unity uses by default
+    pub fn new(object_name: String, version: UnityVersion) -> Self {
+        Self {
+            object_name: AlignedString(object_name),
+            forced_fallback_format: TextureFormat::RGBA32.0,
+            downscale_fallback: U8Bool(false),
+            is_alpha_channel_optional: (version >= UnityVersion(2020, 2)).then_some(U8Bool(false)),
+            width: 0,
+            height: 0,
+            complete_image_size: 0,
+            mips_stripped: 0,
+            texture_format: TextureFormat::RGBA32,
+            mip_count: 1,
+            is_readable: U8Bool(false),
+            is_pre_processed: U8Bool(false),
+            ignore_mipmap_limit: U8Bool(false),
+            mipmap_limit_group_name: (version >= UnityVersion(2022, 2))
+                .then(|| MipmapLimitGroupName { name: AlignedString(String::new()) }),
+            streaming_mipmaps: U8Bool(false),
+            streaming_mipmaps_priority: 0,
+            image_count: 1,
+            texture_dimension: 2,
+            texture_settings: GLTextureSettings {
+                filter_mode: 1,
+                aniso: 1,
+                mip_bias: 0.0,
+                wrap_u: 1,
+                wrap_v: 1,
+                wrap_w: 1,
+            },
+            lightmap_format: 0,
+            color_space: 1,
+            platform_blob: (version >= UnityVersion(2020, 2)).then(ByteArray::default),
+            image_data: ByteArray::default(),
+            stream_data: StreamingInfo { offset: 0, size: 0, path: AlignedString(String::new()) },
+        }
+    }
+
+    /// Replaces the image of this texture with the given RGBA pixels (top row first). The pixels
+    /// are encoded in the current format of the texture if possible, otherwise the texture is
+    /// switched to RGBA32. If the texture had mipmaps, a new mip chain is generated. The image data
+    /// is always stored inline, so any reference to a streamed resource is removed.
+    pub fn replace_image(&mut self, width: usize, height: usize, pixels: &[u8]) -> anyhow::Result<()> {
+        let expected = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(4));
+        if width == 0 || height == 0 || expected != Some(pixels.len()) {
+            anyhow::bail!("Invalid image dimensions {}x{} for {} bytes of pixel data", width, height, pixels.len());
+        }
+        if !self.texture_format.is_encodable() {
+            self.texture_format = TextureFormat::RGBA32;
+        }
+
+        // Unity stores the bottom row first
+        let mut level: Vec<u8> = pixels.chunks_exact(width * 4).rev().flatten().copied().collect();
+        let (mut level_width, mut level_height) = (width, height);
+        let mut data = self.texture_format.encode(&level)?;
+        let mut mip_count = 1;
+        if self.mip_count > 1 {
+            while level_width > 1 || level_height > 1 {
+                (level, level_width, level_height) = downscale(&level, level_width, level_height);
+                data.extend(self.texture_format.encode(&level)?);
+                mip_count += 1;
+            }
+        }
+
+        self.width = width as i32;
+        self.height = height as i32;
+        self.mip_count = mip_count;
+        self.mips_stripped = 0;
+        self.complete_image_size = data.len() as u32;
+        self.image_data = ByteArray { data };
+        self.stream_data = StreamingInfo { offset: 0, size: 0, path: AlignedString(String::new()) };
+        Ok(())
+    }
+
+    /// Decodes the first mip level into RGBA pixels with the top row first. `data` is the image
+    /// data of the texture, which is either stored inline or as a streamed resource.
+    pub fn decode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if self.width <= 0 || self.height <= 0 {
+            anyhow::bail!("Invalid texture dimensions {}x{}", self.width, self.height);
+        }
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let pixels = self.texture_format.decode(data, width, height)?;
+        Ok(pixels.chunks_exact(width * 4).rev().flatten().copied().collect())
+    }
+}
+
+/// Halves the size of an RGBA image by averaging blocks of 2x2 pixels, which is how the next level
+/// of a mip chain is generated.
+fn downscale(pixels: &[u8], width: usize, height: usize) -> (Vec<u8>, usize, usize) {
+    let new_width = (width / 2).max(1);
+    let new_height = (height / 2).max(1);
+    let mut out = Vec::with_capacity(new_width * new_height * 4);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let xs = [(x * 2).min(width - 1), (x * 2 + 1).min(width - 1)];
+            let ys = [(y * 2).min(height - 1), (y * 2 + 1).min(height - 1)];
+            for channel in 0..4 {
+                let sum: u32 = ys.iter()
+                    .flat_map(|&sy| xs.iter().map(move |&sx| (sy * width + sx) * 4 + channel))
+                    .map(|i| pixels[i] as u32)
+                    .sum();
+                out.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    (out, new_width, new_height)
+}
@@ -134,4 +134,39 @@ impl BinRead for AlignedString {
 
 impl Display for AlignedString {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { f.write_str(&self.0) }
-}
\ No newline at end of file
+}
+
+/// Length prefixed byte array. The data is followed by padding to the next 4-byte boundary.
+#[binrw]
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ByteArray {
+    #[bw(calc = data.len() as u32)]
+    len: u32,
+    #[br(count = len)]
+    #[brw(align_after = 4)]
+    pub data: Vec<u8>,
+}
+
+/// Major and minor version of the Unity engine an assets file was built with. Used to pick the
+/// object layout where it differs between versions.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct UnityVersion(pub u32, pub u32);
+
+impl UnityVersion {
+    /// Parses a version string as stored in assets files, e.g. `2021.3.16f1`
+    pub fn parse(version: &str) -> anyhow::Result<Self> {
+        let mut parts = version.split('.');
+        let mut next = || -> anyhow::Result<u32> {
+            parts.next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid unity version: {}", version))
+        };
+        Ok(UnityVersion(next()?, next()?))
+    }
+}
+
+impl Display for UnityVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.0, self.1)
+    }
+}